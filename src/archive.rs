@@ -0,0 +1,59 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    batch,
+    cli::{self, Options},
+    convert_reader_counted, ShapeCounts,
+};
+
+// Converts every .blk entry into out_dir, keeping the archive's folder layout.
+// Returns (entry name, output path, shape counts) for each converted entry.
+pub fn convert_zip(
+    archive_path: &Path,
+    out_dir: &Path,
+    opts: &Options,
+) -> Result<Vec<(String, PathBuf, ShapeCounts)>> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(archive_path)?)?;
+    let mut written = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        // enclosed_name rejects absolute paths and ".." components
+        let Some(entry_path) = entry.enclosed_name() else {
+            continue;
+        };
+        let is_blk = entry_path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("blk"));
+        if !entry.is_file() || !is_blk {
+            continue;
+        }
+        if entry.size() > opts.max_size {
+            return Err(anyhow!(
+                "{}: entry is too large: {} bytes (limit is {} bytes, see --max-size)",
+                entry_path.display(),
+                entry.size(),
+                opts.max_size
+            ));
+        }
+
+        let (json, counts) =
+            convert_reader_counted(&mut entry, &entry_path.display().to_string(), opts).map_err(|e| anyhow!("{}: {}", entry_path.display(), e))?;
+
+        let output_path = out_dir.join(entry_path.with_extension("json"));
+        if output_path == out_dir.join(batch::MANIFEST_NAME) {
+            return Err(anyhow!("{} would overwrite the batch manifest", entry_path.display()));
+        }
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        cli::write_output(&output_path, &json, opts)?;
+        written.push((entry_path.display().to_string(), output_path, counts));
+    }
+
+    Ok(written)
+}
@@ -0,0 +1,203 @@
+use anyhow::{anyhow, Result};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::{self, Options},
+    convert_text_counted, ShapeCounts,
+};
+
+const BLK_EXTENSIONS: &[&str] = &["blk", "txt"];
+
+// --ext replaces the default extensions rather than adding to them
+fn is_blk_file(path: &Path, opts: &Options) -> bool {
+    let Some(ext) = path.extension().and_then(|ext| ext.to_str()) else {
+        return false;
+    };
+    match &opts.extensions {
+        Some(extensions) => extensions.iter().any(|blk| ext.eq_ignore_ascii_case(blk)),
+        None => BLK_EXTENSIONS.iter().any(|blk| ext.eq_ignore_ascii_case(blk)),
+    }
+}
+
+// Every BLK file under dir, sorted so runs are reproducible
+pub fn collect_inputs(dir: &Path, opts: &Options) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if is_blk_file(&path, opts) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+// Pairs each input with its output: next to it, or mirrored under out_dir
+pub fn plan(inputs: &[PathBuf], root: &Path, out_dir: Option<&Path>) -> Result<Vec<(PathBuf, PathBuf)>> {
+    plan_with(inputs, |json_path| match out_dir {
+        Some(out_dir) => out_dir.join(json_path.strip_prefix(root).unwrap_or(json_path)),
+        None => json_path.to_path_buf(),
+    })
+}
+
+// Listed files share no root, so under out_dir they go by file name alone
+pub fn plan_flat(inputs: &[PathBuf], out_dir: Option<&Path>) -> Result<Vec<(PathBuf, PathBuf)>> {
+    plan_with(inputs, |json_path| match (out_dir, json_path.file_name()) {
+        (Some(out_dir), Some(name)) => out_dir.join(name),
+        _ => json_path.to_path_buf(),
+    })
+}
+
+fn plan_with(inputs: &[PathBuf], output_for: impl Fn(&Path) -> PathBuf) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut jobs = Vec::new();
+    let mut claimed = BTreeMap::new();
+
+    for input in inputs {
+        let output = output_for(&input.with_extension("json"));
+        // With --ext json the output can name an input, which the write would replace
+        if inputs.contains(&output) {
+            return Err(anyhow!(
+                "Converting {} would overwrite the input {}",
+                input.display(),
+                output.display()
+            ));
+        }
+        // Two inputs writing one file would race once conversions run in parallel
+        if let Some(other) = claimed.insert(output.clone(), input) {
+            return Err(anyhow!(
+                "{} and {} would both write {}",
+                other.display(),
+                input.display(),
+                output.display()
+            ));
+        }
+        jobs.push((input.clone(), output));
+    }
+
+    Ok(jobs)
+}
+
+// Shells on Windows pass wildcards through unexpanded
+pub fn is_glob(input: &str) -> bool {
+    input.contains(['*', '?', '[']) && !Path::new(input).exists()
+}
+
+// The directory part of a pattern before its first wildcard, e.g. "assets" for "assets/*.blk"
+pub fn glob_root(pattern: &str) -> PathBuf {
+    let literal = &pattern[..pattern.find(['*', '?', '[']).unwrap_or(pattern.len())];
+    match literal.rfind(['/', '\\']) {
+        Some(end) => PathBuf::from(&pattern[..end.max(1)]),
+        None => PathBuf::from("."),
+    }
+}
+
+#[cfg(feature = "glob")]
+pub fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in glob::glob(pattern).map_err(|e| anyhow!("Invalid pattern {}: {}", pattern, e))? {
+        let path = path?;
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    if files.is_empty() {
+        return Err(anyhow!("No files match {}", pattern));
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(not(feature = "glob"))]
+pub fn expand_glob(_pattern: &str) -> Result<Vec<PathBuf>> {
+    Err(anyhow!("This build has no wildcard support (enable the \"glob\" feature)"))
+}
+
+// A --files-from list: one path per line, skipping blank lines and # comments
+pub fn read_list(text: &str) -> Vec<PathBuf> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect()
+}
+
+fn convert_one(input: &Path, output: &Path, opts: &Options) -> Result<ShapeCounts> {
+    let text = cli::read_input(input, opts)?;
+    let (json, counts) = convert_text_counted(&text, &input.display().to_string(), opts)
+        .map_err(|e| cli::pretty_error(e, &input.display().to_string(), &text, opts))?;
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    cli::write_output(output, &json, opts)?;
+    if opts.delete_source {
+        cli::delete_source(input, output)?;
+    }
+    Ok(counts)
+}
+
+// Results come back in job order whether or not the "parallel" feature is on
+pub fn convert_all(jobs: &[(PathBuf, PathBuf)], opts: &Options) -> Vec<Result<ShapeCounts>> {
+    #[cfg(feature = "parallel")]
+    let jobs = jobs.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let jobs = jobs.iter();
+
+    jobs.map(|(input, output)| convert_one(input, output, opts))
+        .collect()
+}
+
+#[derive(Serialize)]
+struct ManifestEntry {
+    output: String,
+    shapes: ShapeCounts,
+    total: usize,
+}
+
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+// Writes dir/manifest.json keyed by input name; outputs are relative to dir
+pub fn write_manifest(dir: &Path, converted: Vec<(String, PathBuf, ShapeCounts)>, opts: &Options) -> Result<PathBuf> {
+    let manifest: BTreeMap<String, ManifestEntry> = converted
+        .into_iter()
+        .map(|(input, output, shapes)| {
+            let output = output.strip_prefix(dir).unwrap_or(&output).display().to_string();
+            let total = shapes.values().sum();
+            (input, ManifestEntry { output, shapes, total })
+        })
+        .collect();
+
+    let path = dir.join(MANIFEST_NAME);
+    cli::write_output(&path, &serde_json::to_string_pretty(&manifest)?, opts)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_output_never_replaces_an_input() {
+        let inputs = [PathBuf::from("maps/a.json"), PathBuf::from("maps/b.blk")];
+        let e = plan(&inputs, Path::new("maps"), None).unwrap_err();
+        assert_eq!(e.to_string(), "Converting maps/a.json would overwrite the input maps/a.json");
+
+        let inputs = [PathBuf::from("maps/a.blk"), PathBuf::from("maps/a.json")];
+        assert!(plan(&inputs, Path::new("maps"), None).is_err());
+        // Elsewhere, under -o, the same names are fine
+        assert!(plan(&inputs[..1], Path::new("maps"), Some(Path::new("out"))).is_ok());
+    }
+}
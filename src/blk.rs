@@ -0,0 +1,196 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+use crate::{
+    parser::DRAW_BLOCKS,
+    shape::{Attrs, Field, Point, Shape},
+};
+
+fn p2(point: &Point) -> String {
+    format!("{},{}", point.x, point.y)
+}
+
+// The shared attributes, written after each entry's own
+fn attrs(attrs: &Attrs) -> Vec<String> {
+    let mut fields = Vec::new();
+    if let Some(layer) = attrs.layer {
+        fields.push(format!("layer:i={}", layer));
+    }
+    if let Some(hidden) = attrs.hidden {
+        fields.push(format!("hidden:b={}", hidden));
+    }
+    if let Some(opacity) = attrs.opacity {
+        fields.push(format!("opacity:r={}", opacity));
+    }
+    for (key, value) in &attrs.extra {
+        if key.ends_with(":t") {
+            fields.push(format!("{}=\"{}\"", key, value));
+        } else {
+            fields.push(format!("{}={}", key, value));
+        }
+    }
+    fields
+}
+
+// BLK has no triangles, so they go back into drawQuads
+fn block_kind(shape: &Shape) -> &'static str {
+    match shape {
+        Shape::Triangle { .. } => "quad",
+        _ => shape.kind(),
+    }
+}
+
+// An entry's name and its `key:type=value` attributes, shared ones last
+fn entry(shape: &Shape) -> (&str, Vec<String>) {
+    let (name, mut fields) = match shape {
+        Shape::Line {
+            start,
+            end,
+            color,
+            width,
+            moves,
+            ..
+        } => {
+            let mut fields = vec![format!("line:p4={},{}", p2(start), p2(end))];
+            fields.extend(color.map(|[r, g, b, a]| format!("color:c={},{},{},{}", r, g, b, a)));
+            fields.extend(width.map(|w| format!("width:r={}", w)));
+            fields.extend(moves.map(|m| format!("move:b={}", m)));
+            ("line", fields)
+        }
+        Shape::Quad {
+            pos1,
+            pos2,
+            pos3,
+            pos4,
+            rotation,
+            ..
+        } => {
+            let mut fields = corners([pos1, pos2, pos3, pos4]);
+            fields.extend(rotation.map(|r| format!("rotation:r={}", r)));
+            ("quad", fields)
+        }
+        // The merged corner comes back doubled, as the quad it was made from
+        Shape::Triangle {
+            pos1,
+            pos2,
+            pos3,
+            rotation,
+            ..
+        } => {
+            let mut fields = corners([pos1, pos2, pos3, pos3]);
+            fields.extend(rotation.map(|r| format!("rotation:r={}", r)));
+            ("quad", fields)
+        }
+        Shape::Arc {
+            center,
+            radius,
+            start_angle,
+            end_angle,
+            ..
+        } => (
+            "arc",
+            vec![
+                format!("center:p2={}", p2(center)),
+                format!("radius:r={}", radius),
+                format!("start:r={}", start_angle),
+                format!("end:r={}", end_angle),
+            ],
+        ),
+        Shape::Polygon { points, .. } => {
+            let points: Vec<String> = points.iter().map(p2).collect();
+            ("polygon", vec![format!("points:p2={}", points.join(","))])
+        }
+        Shape::Bezier { p0, p1, p2: p2_, p3, .. } => (
+            "bezier",
+            vec![
+                format!("p0:p2={}", p2(p0)),
+                format!("p1:p2={}", p2(p1)),
+                format!("p2:p2={}", p2(p2_)),
+                format!("p3:p2={}", p2(p3)),
+            ],
+        ),
+        // Never read back from JSON (see Shape::Custom), so only named by its type
+        Shape::Custom { typ, fields, .. } => (
+            typ.as_str(),
+            fields
+                .iter()
+                .map(|(key, field)| match field {
+                    Field::Point(point) => format!("{}:p2={}", key, p2(point)),
+                    Field::Scalar(value) => format!("{}:r={}", key, value),
+                })
+                .collect(),
+        ),
+    };
+    // BLK has one depth per entry, so only a shared one is written back
+    let mut depths = shape.points().into_iter().map(|point| point.z);
+    if let Some(Some(z)) = depths.next()
+        && depths.all(|other| other == Some(z))
+    {
+        fields.push(format!("z:r={}", z));
+    }
+    fields.extend(attrs(shape.attrs()));
+    (name, fields)
+}
+
+fn corners(corners: [&Point; 4]) -> Vec<String> {
+    ["tl", "tr", "br", "bl"]
+        .iter()
+        .zip(corners)
+        .map(|(key, corner)| format!("{}:p2={}", key, p2(corner)))
+        .collect()
+}
+
+// One entry per line inside its block, or under --blk-pretty one attribute
+// per line, indented a level deeper than the entry
+fn layout(shape: &Shape, pretty: bool) -> String {
+    let (name, fields) = entry(shape);
+    if pretty {
+        let fields: String = fields.iter().map(|field| format!("    {};\n", field)).collect();
+        format!("  {}{{\n{}  }}\n", name, fields)
+    } else {
+        let fields: String = fields.iter().map(|field| format!("{};", field)).collect();
+        format!("  {}{{{}}}\n", name, fields)
+    }
+}
+
+// Turns converted JSON back into draw blocks, one entry per line
+pub fn json_to_blk(text: &str, pretty: bool) -> Result<String> {
+    let mut value: serde_json::Value = serde_json::from_str(text)?;
+    // --with-meta output keeps the shape map under "shapes"
+    if value.get("_meta").is_some()
+        && let Some(shapes) = value.get_mut("shapes").map(serde_json::Value::take)
+    {
+        value = shapes;
+    }
+    let data: BTreeMap<String, Shape> = serde_json::from_value(value)?;
+    let mut shapes: Vec<(String, Shape)> = data.into_iter().collect();
+    // Keys are indices, so "10" belongs after "9"
+    shapes.sort_by_key(|(key, _)| key.parse::<usize>().unwrap_or(usize::MAX));
+
+    let mut blocks = Vec::new();
+    for (kind, block_name) in DRAW_BLOCKS {
+        let entries: Vec<String> = shapes
+            .iter()
+            .filter(|(_, shape)| block_kind(shape) == *kind)
+            .map(|(_, shape)| layout(shape, pretty))
+            .collect();
+        if !entries.is_empty() {
+            blocks.push(format!("{}{{\n{}}}", block_name, entries.concat()));
+        }
+    }
+    Ok(blocks.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_is_written_only_when_the_line_had_one() {
+        let line = r#""type": "line", "start": {"x": 0, "y": 1}, "end": {"x": 2, "y": 3}, "selected": false"#;
+        let blk = json_to_blk(&format!(r#"{{"0": {{{}}}}}"#, line), false);
+        assert_eq!(blk.unwrap(), "drawLines{\n  line{line:p4=0,1,2,3;}\n}");
+        let blk = json_to_blk(&format!(r#"{{"0": {{{}, "move": true}}}}"#, line), false);
+        assert!(blk.unwrap().contains("move:b=true;"));
+    }
+}
@@ -0,0 +1,1305 @@
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, BufRead, BufWriter, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    batch,
+    blk::json_to_blk,
+    compare,
+    config::Config,
+    count_shapes,
+    custom::{self, CustomShape},
+    features_text, kept_comments, order_shapes, parse_indexed,
+    parser::{
+        count_entries, declared_units, is_balanced, parse_input, Comment, ParseError, TooManyShapes, DRAW_BLOCKS,
+        ENTRY_KINDS,
+    },
+    profile, roundtrip, sanitize, schema,
+    shape::Shape,
+    stream_json, summarize, svg, to_output, ShapeCounts, SHAPE_TYPES,
+};
+
+const DEFAULT_MAX_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(PartialEq)]
+pub enum Command {
+    Convert,
+    Features,
+    Roundtrip,
+    Schema,
+    TsTypes,
+    Compare,
+    Repl,
+    Count,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum InputFormat {
+    Blk,
+    Json,
+}
+
+// Which way y points in the BLK; screen coordinates (y down) are flipped
+#[derive(PartialEq, Clone, Copy)]
+pub enum CoordSystem {
+    Screen,
+    Cartesian,
+}
+
+impl CoordSystem {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "screen" => Some(CoordSystem::Screen),
+            "cartesian" => Some(CoordSystem::Cartesian),
+            _ => None,
+        }
+    }
+}
+
+// What BLK input is converted to
+#[derive(PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    Json,
+    Dxf,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Dxf => "dxf",
+        }
+    }
+}
+
+pub struct Options {
+    pub command: Command,
+    pub inputs: Vec<String>,
+    pub output: Option<PathBuf>,
+    pub apply_transform: bool,
+    pub int_coords: bool,
+    pub max_size: u64,
+    pub trailing_newline: bool,
+    pub type_map: BTreeMap<String, String>,
+    // Other names for the DRAW_BLOCKS blocks, alias to block, e.g. draw_lines -> drawLines
+    pub block_aliases: BTreeMap<String, String>,
+    pub zero_pad: usize,
+    pub range: Option<Range<usize>>,
+    pub input_format: Option<InputFormat>,
+    pub delete_source: bool,
+    pub sort_by_layer: bool,
+    pub thumbnail: Option<PathBuf>,
+    pub require_convex: bool,
+    pub pretty_error: bool,
+    pub extensions: Option<Vec<String>>,
+    pub skip_hidden: bool,
+    pub stream: bool,
+    pub validate_output: bool,
+    pub normalize: bool,
+    pub files_from: Option<PathBuf>,
+    pub strip_names: bool,
+    pub with_meta: bool,
+    pub keep_comments: bool,
+    pub float_decimals: Option<usize>,
+    pub summary: Option<PathBuf>,
+    pub also_svg: bool,
+    pub fail_on_empty: bool,
+    pub viewport: Option<(f64, f64)>,
+    pub output_format: OutputFormat,
+    pub merge_duplicated_points: bool,
+    pub group_by_attr: Option<String>,
+    pub post: Option<String>,
+    pub keep_keys_as_int: bool,
+    pub select: Option<Regex>,
+    pub with_raw: bool,
+    pub coord_system: Option<CoordSystem>,
+    pub profile: bool,
+    pub strict_numbers: bool,
+    pub centroid: bool,
+    pub select_indices: BTreeSet<usize>,
+    pub blk_pretty: bool,
+    pub lines_as_pairs: bool,
+    pub max_shapes: Option<usize>,
+    pub allow_partial: bool,
+    pub canonicalize_json: bool,
+    pub shapes_file: Option<PathBuf>,
+    // A JSON pointer such as "/5"
+    pub extract: Option<String>,
+    pub collapse_whitespace: bool,
+    pub uuid: bool,
+    pub uuid_deterministic: bool,
+    pub unique_ids: bool,
+    pub quiet: bool,
+    pub simplify: Option<f64>,
+    // Loaded from shapes_file once every option is in
+    pub custom_shapes: Vec<CustomShape>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            command: Command::Convert,
+            inputs: Vec::new(),
+            output: None,
+            apply_transform: false,
+            int_coords: false,
+            max_size: DEFAULT_MAX_SIZE,
+            trailing_newline: false,
+            type_map: BTreeMap::new(),
+            block_aliases: BTreeMap::new(),
+            zero_pad: 0,
+            range: None,
+            input_format: None,
+            delete_source: false,
+            sort_by_layer: false,
+            thumbnail: None,
+            require_convex: false,
+            pretty_error: false,
+            extensions: None,
+            skip_hidden: false,
+            stream: false,
+            validate_output: false,
+            normalize: false,
+            files_from: None,
+            strip_names: false,
+            with_meta: false,
+            keep_comments: false,
+            float_decimals: None,
+            summary: None,
+            also_svg: false,
+            fail_on_empty: false,
+            viewport: None,
+            output_format: OutputFormat::Json,
+            merge_duplicated_points: false,
+            group_by_attr: None,
+            post: None,
+            keep_keys_as_int: false,
+            select: None,
+            with_raw: false,
+            coord_system: None,
+            profile: false,
+            strict_numbers: false,
+            centroid: false,
+            select_indices: BTreeSet::new(),
+            blk_pretty: false,
+            lines_as_pairs: false,
+            max_shapes: None,
+            allow_partial: false,
+            canonicalize_json: false,
+            shapes_file: None,
+            extract: None,
+            collapse_whitespace: false,
+            uuid: false,
+            uuid_deterministic: false,
+            unique_ids: false,
+            quiet: false,
+            simplify: None,
+            custom_shapes: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut opts = Options::default();
+        let args: Vec<String> = args.into_iter().collect();
+
+        // Config file options go first so the command line wins. A key given
+        // there at all replaces the config value, even as --set key=false.
+        let given = given_keys(&args);
+        for (key, value) in Config::load().options {
+            if given.contains(&key) {
+                continue;
+            }
+            let value = value.as_str().map_or_else(|| value.to_string(), str::to_string);
+            opts.apply_set(&key, &value)
+                .map_err(|e| anyhow!("In the config file: {}", e))?;
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            opts.apply(arg, &mut args)?;
+        }
+        if opts.canonicalize_json && opts.float_decimals.is_some() {
+            return Err(anyhow!("--canonicalize-json writes numbers in their shortest form, so it can't be combined with --float-format"));
+        }
+        if opts.lines_as_pairs && opts.validate_output {
+            return Err(anyhow!("--lines-as-pairs output doesn't follow the schema, so it can't be checked with --validate-output"));
+        }
+        if opts.extract.is_some() && opts.output_format == OutputFormat::Dxf {
+            return Err(anyhow!("--extract picks from JSON output, so it can't be combined with --format dxf"));
+        }
+        // --also-svg writes <name>.svg beside <name>.json, named after the
+        // input file unless -o names the output
+        if opts.also_svg && opts.output.is_none() {
+            let output = match opts.inputs.as_slice() {
+                [input] if opts.command == Command::Convert && opts.post.is_none() && input != "-" && !is_url(input) => {
+                    let output = Path::new(input).with_extension(opts.output_format.extension());
+                    // Never the input itself
+                    Some(output).filter(|output| output != Path::new(input))
+                }
+                _ => None,
+            };
+            opts.output = Some(output.ok_or_else(|| {
+                anyhow!("--also-svg writes files, so it needs -o unless converting a single input file")
+            })?);
+        }
+        if opts.also_svg
+            && let Some(output) = &opts.output
+            && output.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("svg"))
+        {
+            return Err(anyhow!("--also-svg writes the SVG beside the output, so -o can't name an .svg file"));
+        }
+        // --shapes, else a shapes.toml beside config.json if there is one
+        if let Some(path) = opts
+            .shapes_file
+            .clone()
+            .or_else(|| custom::default_path().filter(|path| path.exists()))
+        {
+            opts.custom_shapes = custom::load(&path)?;
+        }
+
+        Ok(opts)
+    }
+
+    // One command-line option, taking its value from args if it has one
+    fn apply(&mut self, arg: String, args: &mut impl Iterator<Item = String>) -> Result<()> {
+        match arg.as_str() {
+            "--features" => self.command = Command::Features,
+            "--schema" => self.command = Command::Schema,
+            "--ts-types" => self.command = Command::TsTypes,
+            "--roundtrip" => {
+                self.command = Command::Roundtrip;
+                self.inputs.push(next_value(args, &arg)?);
+            }
+            "--repl" => self.command = Command::Repl,
+            "--count-only" => self.command = Command::Count,
+            "--compare" => {
+                self.command = Command::Compare;
+                self.inputs.push(next_value(args, &arg)?);
+                self.inputs.push(next_value(args, &arg)?);
+            }
+            "-o" | "--output" => self.output = Some(next_value(args, &arg)?.into()),
+            "--apply-transform" => self.apply_transform = true,
+            "--int-coords" => self.int_coords = true,
+            "--trailing-newline" => self.trailing_newline = true,
+            "--type-map" => self.add_type_map(&next_value(args, &arg)?)?,
+            "--block-alias" => self.add_block_aliases(&next_value(args, &arg)?)?,
+            "--zero-pad" => {
+                let value = next_value(args, &arg)?;
+                self.zero_pad = value
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid --zero-pad width: {}", value))?;
+            }
+            "--range" => self.range = Some(parse_range(&next_value(args, &arg)?)?),
+            "--simplify" => {
+                let value = next_value(args, &arg)?;
+                let epsilon: f64 = value
+                    .parse()
+                    .ok()
+                    .filter(|epsilon: &f64| *epsilon >= 0.0 && epsilon.is_finite())
+                    .ok_or_else(|| anyhow!("Invalid --simplify: {} (expected a distance, e.g. 0.5)", value))?;
+                self.simplify = Some(epsilon);
+            }
+            "--input-format" => {
+                self.input_format = Some(match next_value(args, &arg)?.as_str() {
+                    "blk" => InputFormat::Blk,
+                    "json" => InputFormat::Json,
+                    other => return Err(anyhow!("Unknown --input-format: {} (expected blk or json)", other)),
+                });
+            }
+            "--delete-source" => self.delete_source = true,
+            "--sort-by-layer" => self.sort_by_layer = true,
+            "--thumbnail" => self.thumbnail = Some(next_value(args, &arg)?.into()),
+            "--require-convex" => self.require_convex = true,
+            "--pretty-error" => self.pretty_error = true,
+            "--ext" => self.extensions = Some(parse_extensions(&next_value(args, &arg)?)?),
+            "--skip-hidden" => self.skip_hidden = true,
+            "--stream" => self.stream = true,
+            "--validate-output" => self.validate_output = true,
+            "--normalize" => self.normalize = true,
+            "--files-from" => self.files_from = Some(next_value(args, &arg)?.into()),
+            "--strip-names" => self.strip_names = true,
+            "--with-meta" => self.with_meta = true,
+            "--keep-comments" => {
+                self.with_meta = true;
+                self.keep_comments = true;
+            }
+            "--float-format" => self.float_decimals = Some(parse_float_format(&next_value(args, &arg)?)?),
+            "--summary-json" => self.summary = Some(next_value(args, &arg)?.into()),
+            "--also-svg" => self.also_svg = true,
+            "--fail-on-empty" => self.fail_on_empty = true,
+            "--viewport" => self.viewport = Some(parse_viewport(&next_value(args, &arg)?)?),
+            "--format" => {
+                self.output_format = match next_value(args, &arg)?.as_str() {
+                    "json" => OutputFormat::Json,
+                    "dxf" => OutputFormat::Dxf,
+                    other => return Err(anyhow!("Unknown --format: {} (expected json or dxf)", other)),
+                };
+            }
+            "--merge-duplicated-points-in-quad" => self.merge_duplicated_points = true,
+            "--group-by-attr" => self.group_by_attr = Some(next_value(args, &arg)?),
+            "--post" => self.post = Some(next_value(args, &arg)?),
+            "--keep-keys-as-int" => self.keep_keys_as_int = true,
+            "--with-raw" => self.with_raw = true,
+            "--profile" => self.profile = true,
+            "--quiet" => self.quiet = true,
+            "--strict-numbers" => self.strict_numbers = true,
+            "--centroid" => self.centroid = true,
+            "--blk-pretty" => self.blk_pretty = true,
+            "--lines-as-pairs" => self.lines_as_pairs = true,
+            "--allow-partial" => self.allow_partial = true,
+            "--canonicalize-json" => self.canonicalize_json = true,
+            "--collapse-whitespace-in-names" => self.collapse_whitespace = true,
+            "--uuid" => self.uuid = true,
+            "--unique-ids" => self.unique_ids = true,
+            "--uuid-deterministic" => {
+                self.uuid = true;
+                self.uuid_deterministic = true;
+            }
+            "--shapes" => self.shapes_file = Some(next_value(args, &arg)?.into()),
+            "--extract" => {
+                let pointer = next_value(args, &arg)?;
+                if !pointer.is_empty() && !pointer.starts_with('/') {
+                    return Err(anyhow!("Invalid --extract: {} (expected a JSON pointer such as /5)", pointer));
+                }
+                self.extract = Some(pointer);
+            }
+            "--max-shapes" => {
+                let value = next_value(args, &arg)?;
+                self.max_shapes = Some(
+                    value
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid --max-shapes: {} (expected a number)", value))?,
+                );
+            }
+            "--select-indices" => self.select_indices = parse_indices(&next_value(args, &arg)?)?,
+            "--coord-system" => {
+                let value = next_value(args, &arg)?;
+                self.coord_system = Some(CoordSystem::from_name(&value).ok_or_else(|| {
+                    anyhow!("Unknown --coord-system: {} (expected screen or cartesian)", value)
+                })?);
+            }
+            "--select" => {
+                let pattern = next_value(args, &arg)?;
+                // The whole name has to match, not just part of it
+                self.select = Some(
+                    Regex::new(&format!("^(?:{})$", pattern))
+                        .map_err(|e| anyhow!("Invalid --select pattern: {} ({})", pattern, e))?,
+                );
+            }
+            "--max-size" => self.max_size = parse_size(&next_value(args, &arg)?)?,
+            "--set" => {
+                let value = next_value(args, &arg)?;
+                let (key, value) = value
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Invalid --set: {} (expected KEY=VALUE)", value))?;
+                self.apply_set(key.trim(), value.trim())?;
+            }
+            "-" => self.inputs.push(arg),
+            flag if flag.starts_with('-') => return Err(anyhow!("Unknown option: {}", flag)),
+            _ => self.inputs.push(arg),
+        }
+
+        Ok(())
+    }
+
+    // `key=value` stands for `--key value`, or just `--key` for a switch set
+    // to true; false leaves the switch off
+    fn apply_set(&mut self, key: &str, value: &str) -> Result<()> {
+        let flag = format!("--{}", key.trim_start_matches('-'));
+        let mut args = if !is_switch(&flag)? {
+            vec![flag, value.to_string()]
+        } else {
+            match value {
+                "true" => vec![flag],
+                "false" => Vec::new(),
+                _ => return Err(anyhow!("Invalid value for {}: {} (expected true or false)", key, value)),
+            }
+        }
+        .into_iter();
+        while let Some(arg) = args.next() {
+            self.apply(arg, &mut args)?;
+        }
+        Ok(())
+    }
+
+    // Accepts "line=segment" or several comma-separated pairs
+    fn add_type_map(&mut self, value: &str) -> Result<()> {
+        for pair in value.split(',') {
+            let (kind, typ) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid --type-map entry: {}", pair))?;
+            let kind = kind.trim();
+            if !SHAPE_TYPES.contains(&kind) {
+                return Err(anyhow!("Unknown shape type in --type-map: {}", kind));
+            }
+            if kind == "custom" {
+                return Err(anyhow!("Custom shapes take their type from shapes.toml, not --type-map"));
+            }
+            self.type_map.insert(kind.to_string(), typ.trim().to_string());
+        }
+        Ok(())
+    }
+
+    // Accepts "draw_lines=drawLines" or several comma-separated pairs
+    fn add_block_aliases(&mut self, value: &str) -> Result<()> {
+        for pair in value.split(',') {
+            let (alias, block) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid --block-alias entry: {} (expected ALIAS=BLOCK)", pair))?;
+            let (alias, block) = (alias.trim(), block.trim());
+            if alias.is_empty() {
+                return Err(anyhow!("Invalid --block-alias entry: {} (expected ALIAS=BLOCK)", pair));
+            }
+            let Some((_, block)) = DRAW_BLOCKS.iter().find(|(_, name)| name.eq_ignore_ascii_case(block)) else {
+                return Err(anyhow!("Unknown block in --block-alias: {} (expected e.g. drawLines)", block));
+            };
+            // Block names already match in any case, and an entry name would make
+            // every entry a block of its own
+            if DRAW_BLOCKS.iter().any(|(_, name)| name.eq_ignore_ascii_case(alias))
+                || ENTRY_KINDS.iter().any(|kind| kind.eq_ignore_ascii_case(alias))
+            {
+                return Err(anyhow!("--block-alias {}: that is already a block or entry name", alias));
+            }
+            self.block_aliases.insert(alias.to_string(), block.to_string());
+        }
+        Ok(())
+    }
+
+    // The emitted "type" string for a shape kind
+    pub fn type_name(&self, kind: &str) -> String {
+        self.type_map
+            .get(kind)
+            .cloned()
+            .unwrap_or_else(|| kind.to_string())
+    }
+
+    // --thumbnail, --summary-json and --also-svg, written beside a single conversion
+    fn wants_extras(&self) -> bool {
+        self.thumbnail.is_some() || self.summary.is_some() || self.also_svg
+    }
+
+    // The window only opens for a plain conversion with nothing to convert
+    pub fn wants_gui(&self) -> bool {
+        self.command == Command::Convert && self.inputs.is_empty() && self.files_from.is_none()
+    }
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    args.next()
+        .ok_or_else(|| anyhow!("Missing value for {}", flag))
+}
+
+// Whether a flag stands alone, found by trying it on throwaway options:
+// one that takes a value uses up the placeholder, even if it then rejects it
+fn is_switch(flag: &str) -> Result<bool> {
+    let mut rest = vec![String::new()].into_iter();
+    let result = Options::default().apply(flag.to_string(), &mut rest);
+    if rest.len() == 0 {
+        return Ok(false);
+    }
+    result.map(|_| true)
+}
+
+// The option names the command line sets, as --name or --set name=...
+fn given_keys(args: &[String]) -> BTreeSet<String> {
+    let mut keys = BTreeSet::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--set" {
+            if let Some((key, _)) = args.next().and_then(|pair| pair.split_once('=')) {
+                keys.insert(key.trim().trim_start_matches('-').to_string());
+            }
+        } else if arg == "-o" {
+            keys.insert("output".to_string());
+        } else if let Some(key) = arg.strip_prefix("--") {
+            keys.insert(key.to_string());
+        }
+    }
+    keys
+}
+
+// "5..10" is 5 through 9; either end may be left out
+fn parse_range(value: &str) -> Result<Range<usize>> {
+    let invalid = || anyhow!("Invalid --range: {} (expected e.g. 5..10)", value);
+    let (start, end) = value.split_once("..").ok_or_else(invalid)?;
+    let start = match start.trim() {
+        "" => 0,
+        start => start.parse().map_err(|_| invalid())?,
+    };
+    let end = match end.trim() {
+        "" => usize::MAX,
+        end => end.parse().map_err(|_| invalid())?,
+    };
+    Ok(start..end)
+}
+
+// "0,3,5"
+fn parse_indices(value: &str) -> Result<BTreeSet<usize>> {
+    value
+        .split(',')
+        .map(|idx| {
+            idx.trim()
+                .parse()
+                .map_err(|_| anyhow!("Invalid --select-indices: {} (expected e.g. 0,3,5)", value))
+        })
+        .collect()
+}
+
+// "1920x1080"
+fn parse_viewport(value: &str) -> Result<(f64, f64)> {
+    let invalid = || anyhow!("Invalid --viewport: {} (expected e.g. 1920x1080)", value);
+    let (width, height) = value.split_once(['x', 'X']).ok_or_else(invalid)?;
+    let width: f64 = width.trim().parse().map_err(|_| invalid())?;
+    let height: f64 = height.trim().parse().map_err(|_| invalid())?;
+    if !(width > 0.0 && height > 0.0) {
+        return Err(invalid());
+    }
+    Ok((width, height))
+}
+
+// ".3" or printf-style "%.3f", both meaning three decimals
+fn parse_float_format(value: &str) -> Result<usize> {
+    let digits = value.strip_prefix('%').unwrap_or(value);
+    let digits = digits.strip_suffix('f').unwrap_or(digits);
+    digits
+        .strip_prefix('.')
+        .and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| anyhow!("Invalid --float-format: {} (expected e.g. .3)", value))
+}
+
+// "blkx,blk" or ".blkx, .blk"
+fn parse_extensions(value: &str) -> Result<Vec<String>> {
+    value
+        .split(',')
+        .map(|ext| match ext.trim().trim_start_matches('.') {
+            "" => Err(anyhow!("Invalid --ext: {}", value)),
+            ext => Ok(ext.to_string()),
+        })
+        .collect()
+}
+
+// Accepts plain bytes or a K/M/G suffix, e.g. "512K" or "64M"
+fn parse_size(value: &str) -> Result<u64> {
+    let upper = value.trim().to_ascii_uppercase();
+    let (digits, shift) = match upper.as_bytes().last() {
+        Some(b'K') => (&upper[..upper.len() - 1], 10),
+        Some(b'M') => (&upper[..upper.len() - 1], 20),
+        Some(b'G') => (&upper[..upper.len() - 1], 30),
+        _ => (upper.as_str(), 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|size| size.checked_mul(1 << shift))
+        .ok_or_else(|| anyhow!("Invalid size: {}", value))
+}
+
+// Reads a file, stdin for "-" or an http(s) URL, refusing anything over --max-size
+pub fn read_input(input: &Path, opts: &Options) -> Result<String> {
+    profile::time("read", || read_text(input, opts))
+}
+
+fn read_text(input: &Path, opts: &Options) -> Result<String> {
+    let mut content = String::new();
+
+    if input == Path::new("-") {
+        return read_limited(io::stdin(), opts.max_size);
+    }
+    if let Some(url) = input.to_str().filter(|input| is_url(input)) {
+        return fetch_url(url, opts.max_size);
+    }
+
+    // Kept as the cause, so a missing file still exits with the I/O code
+    let read_error = |e: io::Error| {
+        let message = format!("Couldn't read {}: {}", input.display(), e);
+        anyhow::Error::from(e).context(message)
+    };
+    let size = fs::metadata(input).map_err(read_error)?.len();
+    if size > opts.max_size {
+        return Err(anyhow!(
+            "Input is too large: {} bytes (limit is {} bytes, see --max-size)",
+            size,
+            opts.max_size
+        ));
+    }
+    fs::File::open(input)
+        .and_then(|mut file| file.read_to_string(&mut content))
+        .map_err(read_error)?;
+    Ok(content)
+}
+
+// For streams whose size isn't known up front
+pub fn read_limited(reader: impl Read, max_size: u64) -> Result<String> {
+    let mut content = String::new();
+    reader.take(max_size + 1).read_to_string(&mut content)?;
+    if content.len() as u64 > max_size {
+        return Err(anyhow!(
+            "Input exceeds the size limit of {} bytes (see --max-size)",
+            max_size
+        ));
+    }
+    Ok(content)
+}
+
+// Anything that looks like `scheme://`, so other schemes are refused rather
+// than read as a path
+fn is_url(input: &str) -> bool {
+    input.split_once("://").is_some_and(|(scheme, _)| {
+        !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    })
+}
+
+fn fetch_url(url: &str, max_size: u64) -> Result<String> {
+    let scheme = url.split_once("://").map_or("", |(scheme, _)| scheme);
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return Err(anyhow!("Only http and https URLs can be read, not {}", url));
+    }
+    get_url(url, max_size)
+}
+
+#[cfg(feature = "url")]
+fn get_url(url: &str, max_size: u64) -> Result<String> {
+    match ureq::get(url).call() {
+        Ok(response) => read_limited(response.into_reader(), max_size),
+        Err(ureq::Error::Status(code, response)) => {
+            Err(anyhow!("GET {} failed: {} {}", url, code, response.status_text()))
+        }
+        Err(e) => Err(anyhow!("GET {} failed: {}", url, e)),
+    }
+}
+
+#[cfg(not(feature = "url"))]
+fn get_url(_url: &str, _max_size: u64) -> Result<String> {
+    Err(anyhow!("This build has no URL support (enable the \"url\" feature)"))
+}
+
+pub fn write_output(path: &Path, contents: &str, opts: &Options) -> Result<()> {
+    profile::time("write", || {
+        write_atomic(path, |file| {
+            file.write_all(contents.as_bytes())?;
+            if opts.trailing_newline {
+                file.write_all(b"\n")?;
+            }
+            Ok(())
+        })
+    })
+}
+
+// Writes into a temporary file next to the output and renames it over the
+// output, so neither concurrent conversions to the same name nor a crash
+// halfway through ever leave a partial file; until the rename, the previous
+// file stays as it was
+pub fn write_atomic(path: &Path, write: impl FnOnce(&mut fs::File) -> Result<()>) -> Result<()> {
+    static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid output path: {}", path.display()))?;
+    let temp = path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name.to_string_lossy(),
+        process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let written = fs::File::create(&temp)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all()?;
+            Ok(())
+        })
+        .and_then(|()| Ok(fs::rename(&temp, path)?));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+// Under --pretty-error, adds the source line and a caret under the failing column
+pub fn pretty_error(e: anyhow::Error, input: &str, text: &str, opts: &Options) -> anyhow::Error {
+    let Some(parse_error) = e.downcast_ref::<ParseError>().filter(|_| opts.pretty_error) else {
+        return e;
+    };
+    // Offsets count from the text parse_input saw
+    let text = sanitize(text);
+    let offset = parse_error.offset.min(text.len());
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+    let line_no = text[..offset].matches('\n').count() + 1;
+    let before = &text[line_start..offset];
+    // Tabs stay tabs so the caret lines up however they are displayed
+    let pad: String = before.chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    let gutter = " ".repeat(line_no.to_string().len());
+
+    // As context, so the ParseError is still there for the exit code
+    let message = format!(
+        "{}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}^",
+        parse_error,
+        gutter,
+        input,
+        line_no,
+        before.chars().count() + 1,
+        gutter,
+        line_no,
+        text[line_start..line_end].trim_end_matches('\r'),
+        gutter,
+        pad
+    );
+    e.context(message)
+}
+
+// For directories --output names a directory mirroring the input tree
+fn convert_dir(dir: &Path, opts: &Options) -> Result<()> {
+    let inputs = batch::collect_inputs(dir, opts)?;
+    let jobs = batch::plan(&inputs, dir, opts.output.as_deref())?;
+    convert_batch(jobs, opts.output.as_deref().unwrap_or(dir), dir, opts)
+}
+
+// Converts every match like a directory, with the pattern's fixed leading
+// directory as the root that --output mirrors
+fn convert_glob(pattern: &str, opts: &Options) -> Result<()> {
+    let root = batch::glob_root(pattern);
+    let inputs = batch::expand_glob(pattern)?;
+    let jobs = batch::plan(&inputs, &root, opts.output.as_deref())?;
+    convert_batch(jobs, opts.output.as_deref().unwrap_or(&root), &root, opts)
+}
+
+// Converts every file named in the list; --output names a flat output directory
+fn convert_list(list: &Path, opts: &Options) -> Result<()> {
+    let inputs = batch::read_list(&read_input(list, opts)?);
+    let jobs = batch::plan_flat(&inputs, opts.output.as_deref())?;
+    let list_dir = list
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    convert_batch(jobs, opts.output.as_deref().unwrap_or(list_dir), Path::new(""), opts)
+}
+
+// After every conversion, e.g. `lines=12 quads=4` on stderr, unless --quiet
+fn report_counts(counts: &ShapeCounts, opts: &Options) {
+    if opts.quiet {
+        return;
+    }
+    let counts: Vec<String> = counts
+        .iter()
+        .filter(|(_, count)| **count > 0)
+        .map(|(kind, count)| format!("{}s={}", kind, count))
+        .collect();
+    eprintln!("{}", if counts.is_empty() { "no shapes".to_string() } else { counts.join(" ") });
+}
+
+// The counts of a batch, summed over its files
+fn total_counts<'a>(counts: impl IntoIterator<Item = &'a ShapeCounts>) -> ShapeCounts {
+    let mut total = count_shapes([]);
+    for (kind, count) in counts.into_iter().flatten() {
+        *total.entry(kind).or_default() += count;
+    }
+    total
+}
+
+// Runs the jobs, reporting failures as it goes, and writes the manifest into
+// out_dir; manifest names are the inputs relative to root
+fn convert_batch(jobs: Vec<(PathBuf, PathBuf)>, out_dir: &Path, root: &Path, opts: &Options) -> Result<()> {
+    let manifest_path = out_dir.join(batch::MANIFEST_NAME);
+    if let Some((input, _)) = jobs.iter().find(|(_, output)| *output == manifest_path) {
+        return Err(anyhow!("{} would overwrite the batch manifest", input.display()));
+    }
+    let results = batch::convert_all(&jobs, opts);
+
+    let total = jobs.len();
+    let mut failed = 0;
+    let mut converted = Vec::new();
+    for ((input, output), result) in jobs.into_iter().zip(results) {
+        match result {
+            Ok(counts) => {
+                let name = input.strip_prefix(root).unwrap_or(&input).display().to_string();
+                converted.push((name, output, counts));
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("Error: {}: {}", input.display(), e);
+            }
+        }
+    }
+    let counts = total_counts(converted.iter().map(|(_, _, counts)| counts));
+    list_outputs(out_dir, converted, opts)?;
+    report_counts(&counts, opts);
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} files failed", failed, total));
+    }
+    Ok(())
+}
+
+// Writes the manifest, then prints every output path and its own. The manifest
+// goes first, so a reader that closes stdout early can't leave it unwritten.
+fn list_outputs(out_dir: &Path, converted: Vec<(String, PathBuf, ShapeCounts)>, opts: &Options) -> Result<()> {
+    let outputs: Vec<PathBuf> = converted.iter().map(|(_, output, _)| output.clone()).collect();
+    let manifest = batch::write_manifest(out_dir, converted, opts)?;
+    let mut stdout = io::stdout().lock();
+    for output in outputs.iter().chain([&manifest]) {
+        writeln!(stdout, "{}", output.display())?;
+    }
+    Ok(())
+}
+
+// For archives --output names a directory, defaulting to the archive's stem
+#[cfg(feature = "zip")]
+fn convert_zip(input: &Path, opts: &Options) -> Result<()> {
+    let out_dir = match &opts.output {
+        Some(dir) => dir.clone(),
+        None => input.with_extension(""),
+    };
+    let converted = crate::archive::convert_zip(input, &out_dir, opts)?;
+    let counts = total_counts(converted.iter().map(|(_, _, counts)| counts));
+    list_outputs(&out_dir, converted, opts)?;
+    report_counts(&counts, opts);
+    if opts.delete_source {
+        fs::remove_file(input)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "zip"))]
+fn convert_zip(_input: &Path, _opts: &Options) -> Result<()> {
+    Err(anyhow!("This build has no zip support (enable the \"zip\" feature)"))
+}
+
+// --post: returns the response status, e.g. "200 OK"
+#[cfg(feature = "post")]
+fn post_json(url: &str, json: &str) -> Result<String> {
+    match ureq::post(url).set("Content-Type", "application/json").send_string(json) {
+        Ok(response) => Ok(format!("{} {}", response.status(), response.status_text())),
+        Err(ureq::Error::Status(code, response)) => {
+            Err(anyhow!("POST {} failed: {} {}", url, code, response.status_text()))
+        }
+        Err(e) => Err(anyhow!("POST {} failed: {}", url, e)),
+    }
+}
+
+#[cfg(not(feature = "post"))]
+fn post_json(_url: &str, _json: &str) -> Result<String> {
+    Err(anyhow!("This build has no HTTP support (enable the \"post\" feature)"))
+}
+
+#[cfg(feature = "thumbnail")]
+fn write_thumbnail(shapes: &[&Shape], path: &Path) -> Result<()> {
+    crate::thumbnail::write_png(shapes, path)
+}
+
+#[cfg(not(feature = "thumbnail"))]
+fn write_thumbnail(_shapes: &[&Shape], _path: &Path) -> Result<()> {
+    Err(anyhow!("This build has no thumbnail support (enable the \"thumbnail\" feature)"))
+}
+
+fn write_extras(data: &BTreeMap<String, Shape>, opts: &Options) -> Result<()> {
+    if let Some(path) = &opts.thumbnail {
+        write_thumbnail(&data.values().collect::<Vec<_>>(), path)?;
+    }
+    if let Some(path) = &opts.summary {
+        let summary = serde_json::to_string_pretty(&summarize(data.values()))?;
+        write_atomic(path, |file| Ok(file.write_all(summary.as_bytes())?))?;
+    }
+    if opts.also_svg
+        && let Some(path) = &opts.output
+    {
+        let svg = svg::to_svg(data.values());
+        write_atomic(&path.with_extension("svg"), |file| Ok(file.write_all(svg.as_bytes())?))?;
+    }
+    Ok(())
+}
+
+// Call only after the output was written; refuses to remove the output itself
+pub fn delete_source(input: &Path, output: &Path) -> Result<()> {
+    if fs::canonicalize(input)? == fs::canonicalize(output)? {
+        return Err(anyhow!("Not deleting {}: it is also the output", input.display()));
+    }
+    fs::remove_file(input)?;
+    Ok(())
+}
+
+pub fn run(opts: &Options) -> Result<()> {
+    if !opts.profile {
+        return run_command(opts);
+    }
+    profile::enable();
+    let result = run_command(opts);
+    profile::report();
+    result
+}
+
+fn run_command(opts: &Options) -> Result<()> {
+    match opts.command {
+        Command::Convert => convert(opts),
+        Command::Features => {
+            writeln!(io::stdout().lock(), "{}", features_text())?;
+            Ok(())
+        }
+        Command::Roundtrip => check_roundtrip(opts),
+        Command::Compare => compare_files(opts),
+        Command::Repl => repl(opts),
+        Command::Count => count_only(opts),
+        Command::Schema => {
+            let schema = serde_json::to_string_pretty(&schema::schema(&opts.custom_shapes))?;
+            writeln!(io::stdout().lock(), "{}", schema)?;
+            Ok(())
+        }
+        Command::TsTypes => {
+            writeln!(io::stdout().lock(), "{}", schema::ts_types(opts))?;
+            Ok(())
+        }
+    }
+}
+
+fn check_roundtrip(opts: &Options) -> Result<()> {
+    let [input] = opts.inputs.as_slice() else {
+        return Err(anyhow!("Expected a single input file"));
+    };
+    let text = read_input(Path::new(input), opts)?;
+    let divergences = roundtrip(&text, opts).map_err(|e| pretty_error(e, input, &text, opts))?;
+    if divergences.is_empty() {
+        writeln!(io::stdout().lock(), "Roundtrip OK: {}", input)?;
+        return Ok(());
+    }
+    for divergence in &divergences {
+        eprintln!("{}", divergence);
+    }
+    Err(anyhow!("Roundtrip failed: {} shape(s) diverged", divergences.len()))
+}
+
+// --repl: reads stdin until the braces typed so far balance, then prints the
+// JSON of that snippet; prompts go to stderr so stdout stays JSON
+fn repl(opts: &Options) -> Result<()> {
+    let mut stdin = io::stdin().lock();
+    let mut snippet = String::new();
+    loop {
+        eprint!("{} ", if snippet.is_empty() { ">" } else { "." });
+        io::stderr().flush()?;
+        if stdin.read_line(&mut snippet)? == 0 {
+            return Ok(());
+        }
+        if snippet.trim().is_empty() {
+            snippet.clear();
+            continue;
+        }
+        if !snippet.contains('{') || !is_balanced(&snippet) {
+            continue;
+        }
+        match repl_json(&snippet, opts) {
+            Ok(json) => writeln!(io::stdout().lock(), "{}", json)?,
+            Err(e) => eprintln!("Error: {}", e),
+        }
+        snippet.clear();
+    }
+}
+
+// A bare entry such as line{...} is read as if it were inside a draw block
+fn repl_json(snippet: &str, opts: &Options) -> Result<String> {
+    let wrapped;
+    let text = if parse_input(sanitize(snippet), opts)?.is_empty() {
+        wrapped = format!("drawLines{{{}}}", snippet);
+        &wrapped
+    } else {
+        snippet
+    };
+    to_output(&parse_indexed(text, opts)?, "stdin", declared_units(text).as_deref(), &kept_comments(text, opts), opts)
+}
+
+// Prints one line per differing shape: "-" removed, "+" added, "~" changed
+fn compare_files(opts: &Options) -> Result<()> {
+    let [before, after] = opts.inputs.as_slice() else {
+        return Err(anyhow!("Expected two BLK files to compare"));
+    };
+    let parse = |input: &str| -> Result<Vec<Shape>> {
+        let text = read_input(Path::new(input), opts)?;
+        parse_input(sanitize(&text), opts).map_err(|e| pretty_error(e, input, &text, opts))
+    };
+    let lines = compare(parse(before)?, parse(after)?, opts)?;
+    let mut stdout = io::stdout().lock();
+    if lines.is_empty() {
+        writeln!(stdout, "No differences")?;
+    }
+    for line in &lines {
+        writeln!(stdout, "{}", line)?;
+    }
+    Ok(())
+}
+
+// --count-only: the number of shapes of each kind present, then the total,
+// under each input's name when there are several
+fn count_only(opts: &Options) -> Result<()> {
+    if opts.inputs.is_empty() {
+        return Err(anyhow!("Expected an input file to count"));
+    }
+    for input in &opts.inputs {
+        let text = read_input(Path::new(input), opts)?;
+        let counts = count_entries(sanitize(&text), opts).map_err(|e| pretty_error(e, input, &text, opts))?;
+        let mut stdout = io::stdout().lock();
+        if opts.inputs.len() > 1 {
+            writeln!(stdout, "{}:", input)?;
+        }
+        for (kind, count) in counts.iter().filter(|(_, count)| **count > 0) {
+            writeln!(stdout, "{}: {}", kind, count)?;
+        }
+        writeln!(stdout, "total: {}", counts.values().sum::<usize>())?;
+    }
+    Ok(())
+}
+
+// Converts the input, writing JSON (or BLK for JSON input) to --output or stdout
+fn convert(opts: &Options) -> Result<()> {
+    if let Some(list) = &opts.files_from {
+        if !opts.inputs.is_empty() {
+            return Err(anyhow!("--files-from can't be combined with input files"));
+        }
+        if opts.wants_extras() {
+            return Err(anyhow!("--thumbnail, --summary-json and --also-svg need a single BLK file input"));
+        }
+        if opts.post.is_some() {
+            return Err(anyhow!("--post sends a single conversion, not a list of files"));
+        }
+        return convert_list(list, opts);
+    }
+    if opts.inputs.len() > 1 {
+        return convert_merged(opts);
+    }
+    let [input] = opts.inputs.as_slice() else {
+        return Err(anyhow!("Expected a single input file"));
+    };
+    // A URL names one remote file, even with a `?` query or a .zip path
+    let is_batch = !is_url(input)
+        && (batch::is_glob(input)
+            || Path::new(input).is_dir()
+            || Path::new(input)
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("zip")));
+    if opts.wants_extras() && is_batch {
+        return Err(anyhow!("--thumbnail, --summary-json and --also-svg need a single BLK file input"));
+    }
+    if opts.post.is_some() && is_batch {
+        return Err(anyhow!("--post sends a single conversion, not a directory, archive or wildcard"));
+    }
+    if opts.output_format == OutputFormat::Dxf && is_batch {
+        return Err(anyhow!("--format dxf needs BLK file inputs, not a directory, archive or wildcard"));
+    }
+    if is_batch {
+        if Path::new(input)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+        {
+            return convert_zip(Path::new(input), opts);
+        }
+        if Path::new(input).is_dir() {
+            return convert_dir(Path::new(input), opts);
+        }
+        return convert_glob(input, opts);
+    }
+    let is_json = Path::new(input)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    let format = opts.input_format.unwrap_or(if is_json {
+        InputFormat::Json
+    } else {
+        InputFormat::Blk
+    });
+
+    if opts.delete_source && (input == "-" || is_url(input) || opts.output.is_none()) {
+        return Err(anyhow!("--delete-source needs a file input and an -o output file"));
+    }
+
+    check_post(opts, format == InputFormat::Json)?;
+
+    let text = read_input(Path::new(input), opts)?;
+    if opts.stream && format == InputFormat::Blk {
+        return convert_streaming(input, &text, opts);
+    }
+    // JSON input becomes BLK, which has no shape counts to report
+    let mut counts = None;
+    let converted = match format {
+        InputFormat::Blk => {
+            let data = parse_indexed(&text, opts).map_err(|e| pretty_error(e, input, &text, opts))?;
+            write_extras(&data, opts)?;
+            counts = Some(count_shapes(data.values()));
+            to_output(&data, input, declared_units(&text).as_deref(), &kept_comments(&text, opts), opts)?
+        }
+        InputFormat::Json if opts.wants_extras() => {
+            return Err(anyhow!("--thumbnail, --summary-json and --also-svg need a BLK input"));
+        }
+        InputFormat::Json if opts.output_format == OutputFormat::Dxf => {
+            return Err(anyhow!("--format dxf needs a BLK input"));
+        }
+        InputFormat::Json => json_to_blk(&text, opts.blk_pretty)?,
+    };
+
+    emit(&converted, &opts.inputs, opts)?;
+    if let Some(counts) = counts {
+        report_counts(&counts, opts);
+    }
+    Ok(())
+}
+
+// --stream writes shapes straight to the output as they are parsed
+fn convert_streaming(input: &str, text: &str, opts: &Options) -> Result<()> {
+    if opts.sort_by_layer
+        || opts.wants_extras()
+        || opts.with_meta
+        || opts.output_format != OutputFormat::Json
+        || opts.group_by_attr.is_some()
+        || opts.canonicalize_json
+        || opts.extract.is_some()
+    {
+        return Err(anyhow!(
+            "--stream can't be combined with --sort-by-layer, --thumbnail, --summary-json, --also-svg, --with-meta, --keep-comments, --format dxf, --group-by-attr, --canonicalize-json or --extract"
+        ));
+    }
+    let streamed = |out: &mut dyn Write, newline: bool| -> Result<ShapeCounts> {
+        let mut out = BufWriter::new(out);
+        let counts = stream_json(text, opts, &mut out).map_err(|e| pretty_error(e, input, text, opts))?;
+        if newline {
+            out.write_all(b"\n")?;
+        }
+        out.flush()?;
+        Ok(counts)
+    };
+
+    let counts = match &opts.output {
+        Some(path) => {
+            let mut counts = None;
+            write_atomic(path, |file| {
+                counts = Some(streamed(file, opts.trailing_newline)?);
+                Ok(())
+            })?;
+            if opts.delete_source {
+                delete_source(Path::new(input), path)?;
+            }
+            counts.unwrap_or_default()
+        }
+        None => streamed(&mut io::stdout().lock(), true)?,
+    };
+    report_counts(&counts, opts);
+    Ok(())
+}
+
+// Writes to --output, then deletes the sources if asked, or prints to stdout
+// --post sends the JSON of one conversion in place of writing it
+fn check_post(opts: &Options, json_input: bool) -> Result<()> {
+    if opts.post.is_some()
+        && (opts.output.is_some() || opts.stream || json_input || opts.output_format == OutputFormat::Dxf)
+    {
+        return Err(anyhow!(
+            "--post can't be combined with -o, --stream, --format dxf or JSON input"
+        ));
+    }
+    Ok(())
+}
+
+fn emit(converted: &str, inputs: &[String], opts: &Options) -> Result<()> {
+    if let Some(url) = &opts.post {
+        let response = post_json(url, converted)?;
+        writeln!(io::stdout().lock(), "POST {}: {}", url, response)?;
+        return Ok(());
+    }
+    match &opts.output {
+        Some(path) => {
+            write_output(path, converted, opts)?;
+            if opts.delete_source {
+                for input in inputs {
+                    delete_source(Path::new(input), path)?;
+                }
+            }
+        }
+        None => profile::time("write", || writeln!(io::stdout().lock(), "{}", converted))?,
+    }
+
+    Ok(())
+}
+
+// Several BLK files become one map, later files continuing the indices
+fn convert_merged(opts: &Options) -> Result<()> {
+    if opts.delete_source && (opts.inputs.iter().any(|input| input == "-") || opts.output.is_none()) {
+        return Err(anyhow!("--delete-source needs file inputs and an -o output file"));
+    }
+    check_post(opts, false)?;
+
+    let mut shapes = Vec::new();
+    let mut units = Vec::new();
+    let mut comments = Vec::new();
+    for input in &opts.inputs {
+        let path = Path::new(input);
+        let not_blk = path.is_dir()
+            || opts.input_format == Some(InputFormat::Json)
+            || path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("json") || ext.eq_ignore_ascii_case("zip"));
+        if not_blk {
+            return Err(anyhow!("Only BLK files can be merged: {}", input));
+        }
+        let text = read_input(path, opts)?;
+        shapes.extend(parse_input(sanitize(&text), opts).map_err(|e| pretty_error(e, input, &text, opts))?);
+        // Each input is held to --max-shapes as it is parsed, the merged map here
+        if let Some(max) = opts.max_shapes
+            && shapes.len() > max
+        {
+            return Err(TooManyShapes(max).into());
+        }
+        units.push(declared_units(&text));
+        comments.extend(kept_comments(&text, opts).into_iter().map(|comment| Comment {
+            source: Some(input.clone()),
+            ..comment
+        }));
+    }
+    // One unit for the merged map only if every input agrees on it
+    units.dedup();
+    let units = match units.as_slice() {
+        [units] => units.as_deref(),
+        _ => {
+            if opts.with_meta {
+                eprintln!("Warning: the inputs declare different units, so _meta leaves them out");
+            }
+            None
+        }
+    };
+
+    let data = order_shapes(shapes, opts)?;
+    write_extras(&data, opts)?;
+    emit(&to_output(&data, &opts.inputs.join(", "), units, &comments, opts)?, &opts.inputs, opts)?;
+    report_counts(&count_shapes(data.values()), opts);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_shapes_counts_merged_inputs_together() {
+        let dir = std::env::temp_dir().join(format!("blk-to-json-merge-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let inputs = ["a.blk", "b.blk"].map(|name| {
+            let path = dir.join(name);
+            fs::write(&path, "drawLines{ line{line:p4=0,0,1,1;} line{line:p4=2,2,3,3;} }").unwrap();
+            path.display().to_string()
+        });
+        let opts = Options {
+            inputs: inputs.to_vec(),
+            output: Some(dir.join("merged.json")),
+            max_shapes: Some(3),
+            quiet: true,
+            ..Options::default()
+        };
+        let result = convert_merged(&opts);
+        let written = dir.join("merged.json").exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.unwrap_err().is::<TooManyShapes>());
+        assert!(!written);
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, fs, io::Write, path::PathBuf};
+
+use crate::cli;
+
+// Settings remembered between runs of the window, and option defaults
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub dark_mode: bool,
+    // Applied like --set KEY=VALUE before the command line, e.g. {"float-format": ".3"}
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub options: BTreeMap<String, Value>,
+    // Files the window converted, newest first, for File > Recent
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recent: Vec<PathBuf>,
+}
+
+fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("blk-to-json").join("config.json"))
+}
+
+impl Config {
+    // A missing or unreadable config just means the defaults
+    pub fn load() -> Self {
+        config_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn save(&self) -> Result<()> {
+        let path = config_path().ok_or_else(|| anyhow!("Couldn't find config directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        cli::write_atomic(&path, |file| Ok(file.write_all(json.as_bytes())?))
+    }
+}
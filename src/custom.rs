@@ -0,0 +1,97 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::parser::ENTRY_KINDS;
+
+// A shape kind described in shapes.toml instead of code:
+//
+//   [[shape]]
+//   block = "drawCircles"
+//   entry = "circle"
+//   fields = { center = "point", radius = "scalar" }
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CustomShape {
+    pub block: String,
+    pub entry: String,
+    // The output "type", the entry name when left out
+    #[serde(rename = "type")]
+    pub typ: Option<String>,
+    // Attribute name to how it is read; the output uses the same names
+    pub fields: BTreeMap<String, FieldKind>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    // `key:p2=x,y` or a `key{x:r=..; y:r=..;}` block
+    Point,
+    // `key:r=..`
+    Scalar,
+}
+
+impl CustomShape {
+    pub fn type_name(&self) -> &str {
+        self.typ.as_deref().unwrap_or(&self.entry)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "custom-shapes"), allow(dead_code))]
+struct ShapesFile {
+    #[serde(default)]
+    shape: Vec<CustomShape>,
+}
+
+// Keys every shape writes itself, so no field may take them
+const RESERVED: &[&str] = &["name", "type", "selected", "layer", "hidden", "opacity", "extra", "_raw", "centroid", "uuid"];
+
+// Used when --shapes isn't given and the file exists
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("blk-to-json").join("shapes.toml"))
+}
+
+pub fn load(path: &Path) -> Result<Vec<CustomShape>> {
+    let text = fs::read_to_string(path).map_err(|e| anyhow!("Couldn't read {}: {}", path.display(), e))?;
+    let shapes = parse(&text).map_err(|e| anyhow!("In {}: {}", path.display(), e))?;
+    check(&shapes).map_err(|e| anyhow!("In {}: {}", path.display(), e))?;
+    Ok(shapes)
+}
+
+#[cfg(feature = "custom-shapes")]
+fn parse(text: &str) -> Result<Vec<CustomShape>> {
+    Ok(toml::from_str::<ShapesFile>(text)?.shape)
+}
+
+#[cfg(not(feature = "custom-shapes"))]
+fn parse(_text: &str) -> Result<Vec<CustomShape>> {
+    Err(anyhow!("This build has no custom shape support (enable the \"custom-shapes\" feature)"))
+}
+
+// An entry name the parser already reads, or one defined twice, would
+// turn the same entry into two shapes
+fn check(shapes: &[CustomShape]) -> Result<()> {
+    for (i, shape) in shapes.iter().enumerate() {
+        let entry = &shape.entry;
+        if ENTRY_KINDS.iter().any(|kind| kind.eq_ignore_ascii_case(entry)) {
+            return Err(anyhow!("Shape `{}` is already a built-in entry", entry));
+        }
+        if shapes[..i].iter().any(|other| other.entry.eq_ignore_ascii_case(entry)) {
+            return Err(anyhow!("Shape `{}` is defined twice", entry));
+        }
+        if let Some(key) = shape.fields.keys().find(|key| RESERVED.contains(&key.as_str())) {
+            return Err(anyhow!("Shape `{}`: `{}` can't be a field name", entry, key));
+        }
+        // Outlines, bounds and centroids are made of points
+        if !shape.fields.values().any(|kind| *kind == FieldKind::Point) {
+            return Err(anyhow!("Shape `{}` needs at least one point field", entry));
+        }
+    }
+    Ok(())
+}
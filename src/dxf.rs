@@ -0,0 +1,122 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::shape::{Point, Shape};
+
+// DXF is a flat list of group code / value pairs, each on its own line
+fn pair(out: &mut String, code: u16, value: impl Display) {
+    out.push_str(&format!("{}\n{}\n", code, value));
+}
+
+fn point(out: &mut String, point: &Point, code: u16) {
+    pair(out, code, point.x);
+    pair(out, code + 10, point.y);
+}
+
+// For entities that take a z coordinate; 2D points sit at 0
+fn point3(out: &mut String, p: &Point, code: u16) {
+    point(out, p, code);
+    pair(out, code + 20, p.z.unwrap_or(0.0));
+}
+
+// Entity header: type, subclass markers and the layer (the shape's `layer`, else "0")
+fn start(out: &mut String, entity: &str, subclass: &str, shape: &Shape) {
+    pair(out, 0, entity);
+    pair(out, 100, "AcDbEntity");
+    pair(out, 8, shape.attrs().layer.map_or("0".to_string(), |layer| layer.to_string()));
+    pair(out, 100, subclass);
+}
+
+fn entity(out: &mut String, shape: &Shape) {
+    match shape {
+        Shape::Line { start: from, end, .. } => {
+            start(out, "LINE", "AcDbLine", shape);
+            point3(out, from, 10);
+            point3(out, end, 11);
+        }
+        Shape::Quad {
+            pos1,
+            pos2,
+            pos3,
+            pos4,
+            ..
+        } => {
+            start(out, "LWPOLYLINE", "AcDbPolyline", shape);
+            pair(out, 90, 4);
+            // Closed
+            pair(out, 70, 1);
+            for corner in [pos1, pos2, pos3, pos4] {
+                point(out, corner, 10);
+            }
+        }
+        Shape::Triangle { pos1, pos2, pos3, .. } => {
+            start(out, "LWPOLYLINE", "AcDbPolyline", shape);
+            pair(out, 90, 3);
+            pair(out, 70, 1);
+            for corner in [pos1, pos2, pos3] {
+                point(out, corner, 10);
+            }
+        }
+        // Both count degrees counterclockwise
+        Shape::Arc {
+            center,
+            radius,
+            start_angle,
+            end_angle,
+            ..
+        } => {
+            start(out, "ARC", "AcDbCircle", shape);
+            point3(out, center, 10);
+            pair(out, 40, radius);
+            pair(out, 100, "AcDbArc");
+            pair(out, 50, start_angle);
+            pair(out, 51, end_angle);
+        }
+        Shape::Polygon { points, .. } => {
+            start(out, "LWPOLYLINE", "AcDbPolyline", shape);
+            pair(out, 90, points.len());
+            pair(out, 70, 1);
+            for corner in points {
+                point(out, corner, 10);
+            }
+        }
+        // A cubic B-spline with clamped knots is exactly the Bezier curve
+        Shape::Bezier { p0, p1, p2, p3, .. } => {
+            start(out, "SPLINE", "AcDbSpline", shape);
+            // Planar
+            pair(out, 70, 8);
+            pair(out, 71, 3);
+            pair(out, 72, 8);
+            pair(out, 73, 4);
+            for knot in [0, 0, 0, 0, 1, 1, 1, 1] {
+                pair(out, 40, knot);
+            }
+            for control in [p0, p1, p2, p3] {
+                point3(out, control, 10);
+            }
+        }
+        // DXF has no such kind, so just its points
+        Shape::Custom { .. } => {
+            for p in shape.points() {
+                start(out, "POINT", "AcDbPoint", shape);
+                point3(out, p, 10);
+            }
+        }
+    }
+}
+
+// A minimal DXF: just an ENTITIES section, which CAD viewers accept on its own.
+// Lines become LINE, quads, triangles and polygons closed LWPOLYLINE, arcs
+// ARC, curves SPLINE and custom shapes a POINT per point, coordinates unchanged.
+pub fn to_dxf(data: &BTreeMap<String, Shape>) -> String {
+    let mut out = String::new();
+    pair(&mut out, 0, "SECTION");
+    pair(&mut out, 2, "ENTITIES");
+    for shape in data.values() {
+        entity(&mut out, shape);
+    }
+    pair(&mut out, 0, "ENDSEC");
+    pair(&mut out, 0, "EOF");
+    // Like JSON output, the final newline is left to the writer
+    out.truncate(out.len() - 1);
+    out
+}
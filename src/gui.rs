@@ -0,0 +1,339 @@
+use anyhow::{anyhow, Result};
+use fltk::{
+    app, button,
+    dialog::{self, NativeFileChooser, NativeFileChooserType},
+    enums::{Color, Font, FrameType, Key, Shortcut},
+    frame,
+    group::Pack,
+    menu::{MenuBar, MenuFlag},
+    prelude::*,
+    text::{TextBuffer, TextDisplay},
+    window::Window,
+};
+use std::{
+    cell::{Cell, RefCell},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    cli::{self, Options},
+    config::Config,
+    convert_text_counted, features_text,
+};
+
+// Converts one file, returning the output for preview()
+fn convert_path(path: &Path, opts: &Options) -> Result<String> {
+    // The chooser can hand back folders, or files removed since it listed them
+    if path.is_dir() {
+        return Err(anyhow!("Please select a file, not a folder"));
+    }
+    if !path.is_file() {
+        return Err(anyhow!("Please select a file: it no longer exists"));
+    }
+    // read_input checks the size before reading, so a wrong multi-GB pick is refused at once
+    let content = cli::read_input(path, opts)?;
+    Ok(convert_text_counted(&content, &path.display().to_string(), opts)?.0)
+}
+
+// Writes a previewed output into the downloads directory, returning its path
+fn save_output(path: &Path, json: &str, opts: &Options) -> Result<PathBuf> {
+    let downloads_dir = dirs::download_dir().ok_or_else(|| anyhow!("Couldn't find downloads directory"))?;
+    let filename = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow!("Invalid filename"))?;
+    let output_path = downloads_dir.join(format!("{}.{}", filename, opts.output_format.extension()));
+
+    cli::write_output(&output_path, json, opts)?;
+    if opts.delete_source {
+        cli::delete_source(path, &output_path)?;
+    }
+    Ok(output_path)
+}
+
+// Shows an output read-only until the user saves or skips it; true means Save.
+// The preview is modal, so its Cancel batch button stands in for the main
+// window's Cancel, which can't be clicked meanwhile.
+fn preview(name: &str, json: &str, cancelled: &Rc<Cell<bool>>) -> bool {
+    let mut win = Window::default()
+        .with_size(600, 500)
+        .with_label(&format!("Preview: {}", name));
+
+    let mut display = TextDisplay::new(10, 10, 580, 440, None);
+    display.set_text_font(Font::Courier);
+    let mut buffer = TextBuffer::default();
+    buffer.set_text(json);
+    display.set_buffer(buffer);
+
+    // Enter saves, Esc (closing the window) skips
+    let mut save = button::ReturnButton::new(390, 460, 95, 30, "Save");
+    let mut skip = button::Button::new(495, 460, 95, 30, "Skip");
+    let mut cancel = button::Button::new(10, 460, 120, 30, "Cancel batch");
+    win.end();
+    win.make_modal(true);
+    win.show();
+
+    let saved = Rc::new(Cell::new(false));
+    let (save_flag, mut save_win) = (saved.clone(), win.clone());
+    save.set_callback(move |_| {
+        save_flag.set(true);
+        save_win.hide();
+    });
+    let mut skip_win = win.clone();
+    skip.set_callback(move |_| skip_win.hide());
+    // Skips this file too, and the loop in convert_paths stops after it
+    let (cancel_flag, mut cancel_win) = (cancelled.clone(), win.clone());
+    cancel.set_callback(move |_| {
+        cancel_flag.set(true);
+        cancel_win.hide();
+    });
+
+    while win.shown() {
+        app::wait();
+    }
+    // Hiding a window doesn't free it, and its display leaves the buffer, with
+    // the whole output, to be freed by whoever set it
+    let buffer = display.buffer();
+    Window::delete(win);
+    if let Some(buffer) = buffer {
+        // SAFETY: the only display showing it went with the window
+        unsafe { TextBuffer::delete(buffer) };
+    }
+    saved.get()
+}
+
+fn log_line(log: &mut TextDisplay, line: &str) {
+    let Some(mut buffer) = log.buffer() else {
+        return;
+    };
+    buffer.append(line);
+    buffer.append("\n");
+
+    let lines = log.count_lines(0, buffer.length(), true);
+    log.scroll(lines, 0);
+}
+
+// Where a conversion run reports to, shared by every way of starting one
+#[derive(Clone)]
+struct Progress {
+    log: TextDisplay,
+    cancel: button::Button,
+    cancelled: Rc<Cell<bool>>,
+    running: Rc<Cell<bool>>,
+}
+
+// How many files File > Recent lists
+const RECENT_FILES: usize = 10;
+
+// Everything a conversion started from the window needs
+#[derive(Clone)]
+struct Session {
+    opts: Rc<Options>,
+    progress: Progress,
+    config: Rc<RefCell<Config>>,
+    menu: MenuBar,
+}
+
+fn convert_files(session: &mut Session) {
+    if session.progress.running.get() {
+        return;
+    }
+    let mut dialog = NativeFileChooser::new(NativeFileChooserType::BrowseMultiFile);
+    dialog.set_filter("BLK and Text files\t*.{blk,txt}");
+    dialog.show();
+
+    convert_paths(session, &dialog.filenames());
+}
+
+fn convert_paths(session: &mut Session, paths: &[PathBuf]) {
+    // app::check() below lets the button or a menu item fire again mid-run
+    let progress = &mut session.progress;
+    if progress.running.get() {
+        return;
+    }
+    progress.running.set(true);
+    progress.cancelled.set(false);
+    progress.cancel.activate();
+
+    let mut converted = Vec::new();
+    for (done, path) in paths.iter().enumerate() {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let saved = convert_path(path, &session.opts).and_then(|json| {
+            // Nothing is written until the output was looked at
+            preview(&name, &json, &progress.cancelled).then(|| save_output(path, &json, &session.opts)).transpose()
+        });
+        match saved {
+            Ok(Some(output_path)) => {
+                log_line(&mut progress.log, &format!("DONE: {} -> {}", name, output_path.display()));
+                converted.push(path.clone());
+            }
+            Ok(None) => log_line(&mut progress.log, &format!("SKIPPED: {}", name)),
+            Err(e) => log_line(&mut progress.log, &format!("Error: {}: {}", name, e)),
+        }
+        // Repaint the log between files, and notice Cancel clicks
+        app::check();
+        if progress.cancelled.get() {
+            let message = format!("Cancelled after {} of {} files", done + 1, paths.len());
+            log_line(&mut progress.log, &message);
+            break;
+        }
+    }
+
+    progress.cancel.deactivate();
+    progress.running.set(false);
+    if !converted.is_empty() {
+        remember(session, &converted);
+    }
+}
+
+// Puts the files at the front of File > Recent, the last one converted first
+fn remember(session: &mut Session, paths: &[PathBuf]) {
+    let mut config = session.config.borrow_mut();
+    for path in paths {
+        config.recent.retain(|recent| recent != path);
+        config.recent.insert(0, path.clone());
+    }
+    config.recent.truncate(RECENT_FILES);
+    if let Err(e) = config.save() {
+        log_line(&mut session.progress.log, &format!("Couldn't save settings: {}", e));
+    }
+    drop(config);
+    update_recent(session);
+}
+
+// Rebuilds File > Recent from the config; picking a file converts it again
+fn update_recent(session: &Session) {
+    let mut menu = session.menu.clone();
+    let submenu = menu.find_index("&File/&Recent");
+    if submenu >= 0 {
+        let _ = menu.clear_submenu(submenu);
+    }
+    let recent = session.config.borrow().recent.clone();
+    if recent.is_empty() {
+        menu.add("&File/&Recent/(none)", Shortcut::None, MenuFlag::Inactive, |_| {});
+    }
+    for path in recent {
+        // '/' would start a submenu, '&' a shortcut and '\\' an escape
+        let label = path
+            .display()
+            .to_string()
+            .replace('\\', "\\\\")
+            .replace('/', "\\/")
+            .replace('&', "&&");
+        let mut session = session.clone();
+        menu.add(&format!("&File/&Recent/{}", label), Shortcut::None, MenuFlag::Normal, move |_| {
+            convert_paths(&mut session, std::slice::from_ref(&path))
+        });
+    }
+}
+
+// Recolors the window and every widget drawn with the default colors
+fn apply_theme(dark: bool, win: &mut Window, label: &mut frame::Frame, button: &mut button::Button) {
+    if dark {
+        app::background(0x30, 0x30, 0x30);
+        app::background2(0x20, 0x20, 0x20);
+        app::foreground(0xe0, 0xe0, 0xe0);
+        win.set_color(Color::from_rgb(0x20, 0x20, 0x20));
+        label.set_label_color(Color::from_rgb(0xe0, 0xe0, 0xe0));
+        button.set_color(Color::from_rgb(0xe0, 0xe0, 0xe0));
+        button.set_label_color(Color::Black);
+    } else {
+        app::background(0xc0, 0xc0, 0xc0);
+        app::background2(0xff, 0xff, 0xff);
+        app::foreground(0x00, 0x00, 0x00);
+        win.set_color(Color::White);
+        label.set_label_color(Color::Black);
+        button.set_color(Color::Black);
+        button.set_label_color(Color::White);
+    }
+    app::redraw();
+}
+
+pub fn run(opts: Options) {
+    let app = app::App::default();
+    let mut win = Window::default()
+        .with_size(400, 360)
+        .with_label("BLK to JSON");
+
+    let mut menu = MenuBar::new(0, 0, 400, 25, None);
+
+    let mut pack = Pack::new(100, 35, 200, 110, None);
+    pack.set_spacing(10);
+
+    let mut label = frame::Frame::default()
+        .with_size(0, 40)
+        .with_label("BLK to JSON");
+    label.set_label_size(25);
+    label.set_frame(FrameType::NoBox);
+
+    let mut button = button::Button::default()
+        .with_size(0, 60)
+        .with_label("CONVERT");
+    button.set_label_size(14);
+
+    pack.end();
+
+    let mut log = TextDisplay::new(10, 155, 380, 160, None);
+    log.set_buffer(TextBuffer::default());
+
+    let mut cancel = button::Button::new(150, 322, 100, 30, "Cancel");
+    cancel.deactivate();
+
+    win.end();
+
+    let cancelled = Rc::new(Cell::new(false));
+    let progress = Progress {
+        log,
+        cancel: cancel.clone(),
+        cancelled: cancelled.clone(),
+        running: Rc::new(Cell::new(false)),
+    };
+    cancel.set_callback(move |_| cancelled.set(true));
+
+    // Shared by the button and the File menu items
+    let session = Session {
+        opts: Rc::new(opts),
+        progress,
+        config: Rc::new(RefCell::new(Config::load())),
+        menu: menu.clone(),
+    };
+    let mut menu_session = session.clone();
+    menu.add("&File/&Convert...", Shortcut::Ctrl | 'o', MenuFlag::Normal, move |_| {
+        convert_files(&mut menu_session)
+    });
+    menu.add("&File/&Recent", Shortcut::None, MenuFlag::Submenu, |_| {});
+    update_recent(&session);
+    let mut quit_win = win.clone();
+    menu.add("&File/&Quit", Shortcut::from_key(Key::Escape), MenuFlag::Normal, move |_| {
+        quit_win.hide()
+    });
+
+    let config = session.config.clone();
+    let dark_mode = config.borrow().dark_mode;
+    apply_theme(dark_mode, &mut win, &mut label, &mut button);
+    let flag = if dark_mode {
+        MenuFlag::Toggle | MenuFlag::Value
+    } else {
+        MenuFlag::Toggle
+    };
+    let (mut theme_win, mut theme_label, mut theme_button) = (win.clone(), label.clone(), button.clone());
+    menu.add("&View/&Dark mode", Shortcut::None, flag, move |menu| {
+        let mut config = config.borrow_mut();
+        config.dark_mode = menu.mvalue().is_some_and(|item| item.value());
+        apply_theme(config.dark_mode, &mut theme_win, &mut theme_label, &mut theme_button);
+        if let Err(e) = config.save() {
+            dialog::alert(300, 200, &format!("Couldn't save settings: {}", e));
+        }
+    });
+    menu.add("&Help/&About", Shortcut::None, MenuFlag::Normal, |_| {
+        dialog::message(300, 200, &features_text());
+    });
+
+    win.show();
+
+    let mut button_session = session;
+    button.set_callback(move |_| convert_files(&mut button_session));
+
+    app.run().unwrap();
+}
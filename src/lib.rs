@@ -0,0 +1,718 @@
+use anyhow::{anyhow, Result};
+use parser::{declared_units, parse_input, parse_shapes, Comment};
+use shape::{Shape, FLOAT_DECIMALS, INT_COORDS, LINES_AS_PAIRS};
+use serde::Serialize;
+use serde_json::value::RawValue;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::{self, Read, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "zip")]
+mod archive;
+mod batch;
+mod blk;
+mod cli;
+mod config;
+mod custom;
+mod dxf;
+#[cfg(feature = "gui")]
+mod gui;
+mod parser;
+mod profile;
+mod schema;
+mod shape;
+mod svg;
+#[cfg(feature = "thumbnail")]
+mod thumbnail;
+
+pub use cli::{run, Options};
+// The types of Options' fields, so library callers can set more than the defaults
+pub use cli::{Command, CoordSystem, InputFormat, OutputFormat};
+pub use custom::{CustomShape, FieldKind};
+
+const SHAPE_TYPES: &[&str] = &["line", "quad", "triangle", "arc", "polygon", "bezier", "custom"];
+const OUTPUT_FORMATS: &[&str] = &["json", "blk", "dxf"];
+
+fn features_text() -> String {
+    format!(
+        "BLK to JSON {}\nShapes: {}\nOutput formats: {}",
+        env!("CARGO_PKG_VERSION"),
+        SHAPE_TYPES.join(", "),
+        OUTPUT_FORMATS.join(", ")
+    )
+}
+
+// Keys and names come from position only, so no two shapes can share an index.
+// --range picks from these indices and keeps them, so shape 5 stays "5";
+// --select-indices refers to them too.
+fn index_shapes(shapes: Vec<Shape>, opts: &Options) -> BTreeMap<String, Shape> {
+    shapes
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| opts.range.as_ref().is_none_or(|range| range.contains(idx)))
+        .map(|(idx, mut shape)| {
+            if !opts.strip_names {
+                shape.set_index(idx);
+            }
+            if opts.select_indices.contains(&idx) {
+                shape.select();
+            }
+            (format!("{:0width$}", idx, width = opts.zero_pad), shape)
+        })
+        .collect()
+}
+
+// Some exporters prepend a UTF-8 BOM and pad the end with NUL bytes
+fn sanitize(text: &str) -> &str {
+    text.strip_prefix('\u{feff}')
+        .unwrap_or(text)
+        .trim_end_matches(|c: char| c == '\0' || c.is_whitespace())
+}
+
+// `grouped` says whether --validate-output should expect a map per group
+fn to_json(data: &impl Serialize, grouped: bool, opts: &Options) -> Result<String> {
+    INT_COORDS.set(opts.int_coords);
+    FLOAT_DECIMALS.set(opts.float_decimals);
+    LINES_AS_PAIRS.set(opts.lines_as_pairs);
+    let json = if opts.canonicalize_json {
+        serde_json::to_string_pretty(&Canonical(&serde_json::to_value(data)?))?
+    } else {
+        serde_json::to_string_pretty(data)?
+    };
+    if opts.validate_output {
+        schema::validate(&json, grouped, &opts.custom_shapes)?;
+    }
+    Ok(json)
+}
+
+// --canonicalize-json: every object's keys sorted, whatever order the structs
+// declare their fields in; index keys go in numeric order
+struct Canonical<'a>(&'a serde_json::Value);
+
+impl Serialize for Canonical<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self.0 {
+            serde_json::Value::Object(map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|(a, _), (b, _)| canonical_order(a, b));
+                serializer.collect_map(entries.into_iter().map(|(key, value)| (key, Canonical(value))))
+            }
+            serde_json::Value::Array(items) => serializer.collect_seq(items.iter().map(Canonical)),
+            value => value.serialize(serializer),
+        }
+    }
+}
+
+// Numbers by value ("2" before "10", ignoring --zero-pad), other keys as text
+fn canonical_order(a: &str, b: &str) -> std::cmp::Ordering {
+    let number = |key: &str| {
+        (!key.is_empty() && key.bytes().all(|c| c.is_ascii_digit())).then(|| key.trim_start_matches('0').to_string())
+    };
+    match (number(a), number(b)) {
+        (Some(x), Some(y)) => x.len().cmp(&y.len()).then(x.cmp(&y)).then(a.cmp(b)),
+        _ => a.cmp(b),
+    }
+}
+
+// Number of shapes per kind, listing every kind even when absent
+type ShapeCounts = BTreeMap<&'static str, usize>;
+
+fn count_shapes<'a>(shapes: impl IntoIterator<Item = &'a Shape>) -> ShapeCounts {
+    let mut counts: ShapeCounts = SHAPE_TYPES.iter().map(|kind| (*kind, 0)).collect();
+    for shape in shapes {
+        *counts.entry(shape.kind()).or_default() += 1;
+    }
+    counts
+}
+
+#[derive(Serialize)]
+struct Bounds {
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64,
+}
+
+// --summary-json: aggregate statistics without any shape data
+#[derive(Serialize)]
+struct Summary {
+    total: usize,
+    shapes: ShapeCounts,
+    bounds: Option<Bounds>,
+}
+
+fn summarize<'a>(shapes: impl IntoIterator<Item = &'a Shape> + Clone) -> Summary {
+    let counts = count_shapes(shapes.clone());
+    Summary {
+        total: counts.values().sum(),
+        shapes: counts,
+        bounds: shape::bounds(shapes).map(|(min_x, min_y, max_x, max_y)| Bounds {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        }),
+    }
+}
+
+// Parses the text and applies the ordering options, ready for serializing
+fn parse_indexed(text: &str, opts: &Options) -> Result<BTreeMap<String, Shape>> {
+    let shapes = parse_input(sanitize(text), opts)?;
+    profile::time("order", || order_shapes(shapes, opts))
+}
+
+// Names and ids are not read as shared attributes, so they are among the
+// extras, keyed like `name:t`
+fn is_name(key: &str) -> bool {
+    matches!(key.split(':').next(), Some("name" | "id"))
+}
+
+// Per-shape options: false drops the shape, otherwise it is adjusted in place
+fn keep_shape(shape: &mut Shape, opts: &Options) -> bool {
+    if opts.skip_hidden && shape.attrs().hidden == Some(true) {
+        return false;
+    }
+    // Before --select, so patterns only need single spaces
+    if opts.collapse_whitespace {
+        for (_, value) in shape.attrs_mut().extra.iter_mut().filter(|(key, _)| is_name(key)) {
+            *value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+    }
+    if let Some(select) = &opts.select
+        && !shape
+            .attrs()
+            .extra
+            .iter()
+            .any(|(key, value)| is_name(key) && select.is_match(value))
+    {
+        return false;
+    }
+    if opts.merge_duplicated_points {
+        shape.merge_duplicated_points(opts.type_name("triangle"));
+    }
+    if opts.normalize {
+        shape.normalize_winding();
+    }
+    if let Some(epsilon) = opts.simplify {
+        shape.simplify(epsilon);
+    }
+    if opts.centroid {
+        shape.attrs_mut().centroid = Some(shape.centroid());
+    }
+    true
+}
+
+// --strict-numbers: JSON has no inf or NaN, serde_json would write them as null
+fn check_finite(shape: &Shape, idx: usize) -> Result<()> {
+    match shape.numbers().into_iter().find(|number| !number.is_finite()) {
+        Some(number) => Err(anyhow!(
+            "Shape {} has a non-finite number: {} (rejected by --strict-numbers)",
+            idx,
+            number
+        )),
+        None => Ok(()),
+    }
+}
+
+// --unique-ids: `seen` maps each source id to the first shape that had it
+fn check_unique_id(shape: &Shape, idx: usize, seen: &mut HashMap<String, usize>) -> Result<()> {
+    let id = shape.attrs().extra.iter().find(|(key, _)| key.split(':').next() == Some("id"));
+    if let Some((_, id)) = id
+        && let Some(first) = seen.insert(id.clone(), idx)
+    {
+        return Err(anyhow!("Shapes {} and {} share the id {:?} (rejected by --unique-ids)", first, idx, id));
+    }
+    Ok(())
+}
+
+// --uuid: a random UUID per shape or, under --uuid-deterministic, one hashed
+// from its kind and numbers, so the same geometry gets the same id every run.
+// Each repeat of a shape also hashes how many came before it, so ids stay unique.
+#[derive(Default)]
+struct Uuids {
+    seen: HashMap<String, usize>,
+}
+
+impl Uuids {
+    fn assign(&mut self, shape: &mut Shape, opts: &Options) -> Result<()> {
+        let seed = opts.uuid_deterministic.then(|| {
+            let geometry = format!("{}{:?}", shape.kind(), shape.numbers());
+            let repeats = self.seen.entry(geometry.clone()).or_default();
+            *repeats += 1;
+            format!("{}#{}", geometry, *repeats - 1)
+        });
+        shape.attrs_mut().uuid = Some(new_uuid(seed.as_deref())?);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "uuid")]
+fn new_uuid(seed: Option<&str>) -> Result<String> {
+    let uuid = match seed {
+        Some(seed) => uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_OID, seed.as_bytes()),
+        None => uuid::Uuid::new_v4(),
+    };
+    Ok(uuid.to_string())
+}
+
+#[cfg(not(feature = "uuid"))]
+fn new_uuid(_seed: Option<&str>) -> Result<String> {
+    Err(anyhow!("This build has no UUID support (enable the \"uuid\" feature)"))
+}
+
+// Applies the ordering and validation options to parsed shapes, then indexes them
+fn order_shapes(mut shapes: Vec<Shape>, opts: &Options) -> Result<BTreeMap<String, Shape>> {
+    shapes.retain_mut(|shape| keep_shape(shape, opts));
+    if opts.sort_by_layer {
+        // Stable, so equal layers keep their file order; no layer sorts as 0
+        shapes.sort_by_key(|shape| shape.attrs().layer.unwrap_or(0));
+    }
+    if opts.require_convex && let Some(idx) = shapes.iter().position(|shape| !shape.is_convex()) {
+        return Err(anyhow!("Quad {} is not convex (required by --require-convex)", idx));
+    }
+    if opts.strict_numbers {
+        for (idx, shape) in shapes.iter().enumerate() {
+            check_finite(shape, idx)?;
+        }
+    }
+    if opts.unique_ids {
+        let mut seen = HashMap::new();
+        for (idx, shape) in shapes.iter().enumerate() {
+            check_unique_id(shape, idx, &mut seen)?;
+        }
+    }
+    // Before --range, so a shape's id doesn't depend on which are exported
+    if opts.uuid {
+        let mut uuids = Uuids::default();
+        for shape in &mut shapes {
+            uuids.assign(shape, opts)?;
+        }
+    }
+    let data = index_shapes(shapes, opts);
+    if opts.fail_on_empty && data.is_empty() {
+        return Err(NoShapes.into());
+    }
+    Ok(data)
+}
+
+// --stream: writes each shape as soon as it is parsed instead of building the
+// whole map first. Keys come out in file order ("2" before "10"), so the text
+// can differ from the default output, which sorts keys as strings.
+// Returns the counts of the shapes written.
+fn stream_json(text: &str, opts: &Options, out: &mut impl Write) -> Result<ShapeCounts> {
+    INT_COORDS.set(opts.int_coords);
+    FLOAT_DECIMALS.set(opts.float_decimals);
+    LINES_AS_PAIRS.set(opts.lines_as_pairs);
+    let mut written = 0;
+    let mut counts = count_shapes([]);
+    let mut idx = 0;
+    let mut uuids = Uuids::default();
+    let mut ids = HashMap::new();
+
+    out.write_all(b"{")?;
+    for shape in parse_shapes(sanitize(text), opts) {
+        let mut shape = match shape {
+            Ok(shape) => shape,
+            Err(e) if opts.allow_partial && !e.is::<parser::TooManyShapes>() => {
+                parser::report_partial(&e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if !keep_shape(&mut shape, opts) {
+            continue;
+        }
+        if opts.require_convex && !shape.is_convex() {
+            return Err(anyhow!("Quad {} is not convex (required by --require-convex)", idx));
+        }
+        if opts.strict_numbers {
+            check_finite(&shape, idx)?;
+        }
+        if opts.unique_ids {
+            check_unique_id(&shape, idx, &mut ids)?;
+        }
+        if opts.uuid {
+            uuids.assign(&mut shape, opts)?;
+        }
+        if opts.range.as_ref().is_none_or(|range| range.contains(&idx)) {
+            if !opts.strip_names {
+                shape.set_index(idx);
+            }
+            if opts.select_indices.contains(&idx) {
+                shape.select();
+            }
+            let key = format!("{:0width$}", idx, width = opts.zero_pad);
+            if opts.validate_output {
+                schema::validate_shape(&serde_json::to_value(&shape)?, &key, &opts.custom_shapes)?;
+            }
+            let separator = if written == 0 { "" } else { "," };
+            profile::time("serialize", || {
+                // Matches to_string_pretty, which nests each value two spaces deeper
+                let value = serde_json::to_string_pretty(&shape::Output(&shape))?.replace('\n', "\n  ");
+                write!(out, "{}\n  \"{}\": {}", separator, key, value)
+            })?;
+            written += 1;
+            *counts.entry(shape.kind()).or_default() += 1;
+        }
+        idx += 1;
+    }
+    out.write_all(if written == 0 { b"}" } else { b"\n}" })?;
+    if opts.fail_on_empty && written == 0 {
+        return Err(NoShapes.into());
+    }
+    Ok(counts)
+}
+
+#[derive(Serialize)]
+struct Meta<'a> {
+    source: &'a str,
+    // What the coordinates are measured in, as the BLK declares it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    units: Option<&'a str>,
+    converted_at: String,
+    tool_version: &'static str,
+}
+
+#[derive(Serialize)]
+struct WithMeta<'a, T: Serialize> {
+    #[serde(rename = "_meta")]
+    meta: Meta<'a>,
+    #[serde(rename = "_comments", skip_serializing_if = "Option::is_none")]
+    comments: Option<&'a [Comment]>,
+    shapes: &'a T,
+}
+
+// --keep-comments: the input's comments for _comments, none without it
+fn kept_comments(text: &str, opts: &Options) -> Vec<Comment> {
+    if opts.keep_comments {
+        parser::comments(text)
+    } else {
+        Vec::new()
+    }
+}
+
+// The current time in RFC 3339 UTC, e.g. 2024-05-01T12:00:00Z
+fn utc_timestamp() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, time) = ((secs / 86400) as i64, secs % 86400);
+
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant's days_from_civil
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+// Shapes serialized as a map in the order given, where a BTreeMap would sort keys as text
+struct Ordered<'a>(Vec<(&'a String, &'a Shape)>);
+
+impl Serialize for Ordered<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|&(key, shape)| (key, shape::Output(shape))))
+    }
+}
+
+// Map order: as text ("10" before "2"), or numeric under --keep-keys-as-int.
+// Index keys have no leading zeros (or all the same --zero-pad width), so
+// shorter means smaller.
+fn ordered<'a>(shapes: impl IntoIterator<Item = (&'a String, &'a Shape)>, opts: &Options) -> Ordered<'a> {
+    let mut shapes: Vec<_> = shapes.into_iter().collect();
+    if opts.keep_keys_as_int {
+        shapes.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then(a.cmp(b)));
+    }
+    Ordered(shapes)
+}
+
+// --group-by-attr: each group's shapes under its value, keeping their keys;
+// shapes without the attribute go under ""
+fn group_shapes<'a>(data: &'a BTreeMap<String, Shape>, opts: &Options) -> BTreeMap<&'a str, Ordered<'a>> {
+    let mut groups: BTreeMap<&str, Vec<(&String, &Shape)>> = BTreeMap::new();
+    for (key, shape) in data {
+        let group = shape.attrs().group.as_deref().unwrap_or_default();
+        groups.entry(group).or_default().push((key, shape));
+    }
+    groups
+        .into_iter()
+        .map(|(group, shapes)| (group, ordered(shapes, opts)))
+        .collect()
+}
+
+// The shape map as written out, grouped under --group-by-attr and wrapped
+// with its provenance under --with-meta
+// `source`, `units` and `comments` describe the input for --with-meta
+fn to_output(
+    data: &BTreeMap<String, Shape>,
+    source: &str,
+    units: Option<&str>,
+    comments: &[Comment],
+    opts: &Options,
+) -> Result<String> {
+    profile::time("serialize", || {
+        if opts.output_format == OutputFormat::Dxf {
+            return Ok(dxf::to_dxf(data));
+        }
+        let json = if opts.group_by_attr.is_some() {
+            with_meta(&group_shapes(data, opts), true, source, units, comments, opts)?
+        } else {
+            with_meta(&ordered(data, opts), false, source, units, comments, opts)?
+        };
+        match &opts.extract {
+            Some(pointer) => extract(&json, pointer),
+            None => Ok(json),
+        }
+    })
+}
+
+// --extract: the part of the output at a JSON pointer, exactly as it was
+// written there, only indented as if it were the whole output
+fn extract(json: &str, pointer: &str) -> Result<String> {
+    let missing = || anyhow!("--extract {}: the output has nothing there", pointer);
+    let mut part: &RawValue = serde_json::from_str(json)?;
+    let mut depth = 0;
+    for token in pointer.split('/').skip(1) {
+        let token = token.replace("~1", "/").replace("~0", "~");
+        let found = match part.get().as_bytes().first() {
+            Some(b'{') => serde_json::from_str::<BTreeMap<String, &RawValue>>(part.get())?.remove(&token),
+            Some(b'[') => {
+                let items: Vec<&RawValue> = serde_json::from_str(part.get())?;
+                token.parse::<usize>().ok().and_then(|i| items.get(i).copied())
+            }
+            _ => None,
+        };
+        part = found.ok_or_else(missing)?;
+        depth += 1;
+    }
+    // to_string_pretty nests each level two spaces deeper
+    let indent = " ".repeat(2 * depth);
+    let lines: Vec<&str> = part
+        .get()
+        .lines()
+        .map(|line| line.strip_prefix(indent.as_str()).unwrap_or(line))
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+fn with_meta(
+    shapes: &impl Serialize,
+    grouped: bool,
+    source: &str,
+    units: Option<&str>,
+    comments: &[Comment],
+    opts: &Options,
+) -> Result<String> {
+    if !opts.with_meta {
+        return to_json(shapes, grouped, opts);
+    }
+    let meta = Meta {
+        source,
+        units,
+        converted_at: utc_timestamp(),
+        tool_version: env!("CARGO_PKG_VERSION"),
+    };
+    let comments = opts.keep_comments.then_some(comments);
+    to_json(&WithMeta { meta, comments, shapes }, grouped, opts)
+}
+
+// `source` names the input for --with-meta
+fn convert_text_counted(text: &str, source: &str, opts: &Options) -> Result<(String, ShapeCounts)> {
+    let data = parse_indexed(text, opts)?;
+    let units = declared_units(text);
+    let comments = kept_comments(text, opts);
+    Ok((to_output(&data, source, units.as_deref(), &comments, opts)?, count_shapes(data.values())))
+}
+
+// Anything that reads (a file, a socket, an in-memory Cursor) instead of a path,
+// held to --max-size like stdin
+fn convert_reader_counted(reader: impl Read, source: &str, opts: &Options) -> Result<(String, ShapeCounts)> {
+    let text = cli::read_limited(reader, opts.max_size)?;
+    convert_text_counted(&text, source, opts)
+}
+
+// Converts BLK from a reader to JSON, with every option left at its default
+pub fn convert_reader<R: Read>(reader: R) -> Result<String> {
+    convert_reader_with_opts(reader, "reader", &Options::default())
+}
+
+// As convert_reader, under the given options; `source` names the input for --with-meta
+pub fn convert_reader_with_opts<R: Read>(reader: R, source: &str, opts: &Options) -> Result<String> {
+    Ok(convert_reader_counted(reader, source, opts)?.0)
+}
+
+// Parses, writes and reads back the JSON, describing every shape that changed
+fn roundtrip(text: &str, opts: &Options) -> Result<Vec<String>> {
+    let mut data = parse_indexed(text, opts)?;
+    // Groups only arrange the output map, so no shape carries one back
+    for shape in data.values_mut() {
+        shape.attrs_mut().group = None;
+    }
+    let parsed: BTreeMap<String, Shape> = serde_json::from_str(&to_json(&data, false, opts)?)?;
+
+    let mut divergences = Vec::new();
+    for (key, shape) in &data {
+        match parsed.get(key) {
+            Some(back) if back == shape => {}
+            Some(back) => divergences.push(format!("{}: wrote {:?}, read back {:?}", key, shape, back)),
+            None => divergences.push(format!("{}: missing after reading back", key)),
+        }
+    }
+    for key in parsed.keys().filter(|key| !data.contains_key(*key)) {
+        divergences.push(format!("{}: unexpected shape after reading back", key));
+    }
+    Ok(divergences)
+}
+
+// --compare: shapes are paired up by geometry, ignoring names and position,
+// so inserting one shape only reports that shape. Whatever is left over is
+// "changed" where both files have a shape of its kind at its index, else
+// removed or added. Lines come out by index.
+fn compare(mut before: Vec<Shape>, mut after: Vec<Shape>, opts: &Options) -> Result<Vec<String>> {
+    before.retain_mut(|shape| keep_shape(shape, opts));
+    after.retain_mut(|shape| keep_shape(shape, opts));
+
+    // Each shape pairs with the first equal one not yet taken, so 0.0 matches
+    // -0.0 and a NaN coordinate never matches
+    let mut matched = vec![false; after.len()];
+    let mut removed = Vec::new();
+    for (idx, shape) in before.iter().enumerate() {
+        match (0..after.len()).find(|&other| !matched[other] && after[other] == *shape) {
+            Some(other) => matched[other] = true,
+            None => removed.push(idx),
+        }
+    }
+    let mut added: Vec<usize> = (0..after.len()).filter(|&idx| !matched[idx]).collect();
+
+    let text = |shape: &Shape| serde_json::to_string(shape);
+    let mut lines = Vec::new();
+    for &idx in &removed {
+        match added.binary_search(&idx) {
+            Ok(pos) if before[idx].kind() == after[idx].kind() => {
+                added.remove(pos);
+                lines.push((idx, format!("~ {}: {} -> {}", idx, text(&before[idx])?, text(&after[idx])?)));
+            }
+            _ => lines.push((idx, format!("- {}: {}", idx, text(&before[idx])?))),
+        }
+    }
+    for idx in added {
+        lines.push((idx, format!("+ {}: {}", idx, text(&after[idx])?)));
+    }
+    // Stable, so a removal stays ahead of an addition at the same index
+    lines.sort_by_key(|(idx, _)| *idx);
+    Ok(lines.into_iter().map(|(_, line)| line).collect())
+}
+
+// --fail-on-empty found nothing to write
+#[derive(Debug)]
+struct NoShapes;
+
+impl fmt::Display for NoShapes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("No shapes to write (--fail-on-empty)")
+    }
+}
+
+impl std::error::Error for NoShapes {}
+
+// For scripts: 2 for input that doesn't parse (BLK or JSON), 3 for I/O
+// errors, 4 when --fail-on-empty finds no shapes, 1 for anything else
+pub fn exit_code(e: &anyhow::Error) -> i32 {
+    let is = |matches: fn(&(dyn std::error::Error + 'static)) -> bool| e.chain().any(matches);
+    if is(|cause| cause.is::<parser::ParseError>())
+        || is(|cause| cause.downcast_ref::<serde_json::Error>().is_some_and(|e| !e.is_io()))
+    {
+        2
+    } else if is(|cause| cause.is::<io::Error>()) {
+        3
+    } else if is(|cause| cause.is::<NoShapes>()) {
+        4
+    } else {
+        1
+    }
+}
+
+// A reader such as `head` that stopped early isn't a failure
+pub fn is_broken_pipe(e: &anyhow::Error) -> bool {
+    e.chain()
+        .any(|cause| cause.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe))
+}
+
+#[cfg(feature = "gui")]
+pub fn run_gui(opts: Options) {
+    gui::run(opts);
+}
+
+#[cfg(not(feature = "gui"))]
+pub fn run_gui(_opts: Options) {
+    eprintln!("Error: This build has no window (enable the \"gui\" feature); pass a file to convert");
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn validate_output_catches_a_broken_serializer() {
+        let mut opts = Options {
+            validate_output: true,
+            ..Options::default()
+        };
+        // Real output passes
+        let data = parse_indexed("drawLines{ line{line:p4=0,0,1,1;} }", &opts).unwrap();
+        assert!(to_json(&data, false, &opts).is_ok());
+
+        // Stands in for a serializer that lost a line's end y
+        let broken = BTreeMap::from([(
+            "0",
+            json!({ "type": "line", "start": { "x": 0, "y": 0 }, "end": { "x": 1 }, "selected": false }),
+        )]);
+        let e = to_json(&broken, false, &opts).unwrap_err();
+        assert_eq!(e.to_string(), "Output doesn't match the schema: /0: matches no allowed shape");
+        opts.validate_output = false;
+        assert!(to_json(&broken, false, &opts).is_ok());
+    }
+
+    #[test]
+    fn compare_goes_by_equality() {
+        let opts = Options::default();
+        let shapes = |text: &str| parse_input(text, &opts).unwrap();
+        let same = compare(
+            shapes("drawLines{ line{line:p4=0,1,2,3;} line{line:p4=4,5,6,7;} }"),
+            shapes("drawLines{ line{line:p4=-0,1,2,3;} line{line:p4=4,5,6,7;} }"),
+            &opts,
+        );
+        assert!(same.unwrap().is_empty());
+        let nan = compare(
+            shapes("drawLines{ line{line:p4=0,1,2,nan;} }"),
+            shapes("drawLines{ line{line:p4=0,1,2,nan;} }"),
+            &opts,
+        );
+        assert_eq!(nan.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn convert_reader_reads_from_a_cursor() {
+        let json = convert_reader(io::Cursor::new("drawLines{ line{line:p4=0,1,2,3;} }")).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["0"]["type"], "line");
+        assert_eq!(value["0"]["end"]["y"], 3.0);
+    }
+}
@@ -0,0 +1,1193 @@
+use anyhow::Result;
+use regex::Regex;
+use serde::Serialize;
+use std::{cell::Cell, collections::BTreeMap, fmt, ops::Range};
+
+use crate::{
+    cli::{CoordSystem, Options},
+    custom::{CustomShape, FieldKind},
+    profile,
+    shape::{rotate_around_centroid, Attrs, Field, Point, Shape},
+    ShapeCounts, SHAPE_TYPES,
+};
+
+// Which block holds each shape kind
+pub const DRAW_BLOCKS: &[(&str, &str)] = &[
+    ("line", "drawLines"),
+    ("quad", "drawQuads"),
+    ("arc", "drawArcs"),
+    ("polygon", "drawPolygons"),
+    ("bezier", "drawBeziers"),
+    // Rects are read as quads, so converting back writes them to drawQuads
+    ("rect", "drawRects"),
+];
+
+// A parse failure at a byte offset into the text given to parse_input
+#[derive(Debug)]
+pub struct ParseError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// More shapes than --max-shapes allows; --allow-partial doesn't skip past it
+#[derive(Debug)]
+pub struct TooManyShapes(pub usize);
+
+impl fmt::Display for TooManyShapes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Too many shapes: more than {} (the --max-shapes limit)", self.0)
+    }
+}
+
+impl std::error::Error for TooManyShapes {}
+
+fn error_at(offset: usize, message: String) -> anyhow::Error {
+    ParseError { offset, message }.into()
+}
+
+// Where a slice borrowed from text starts within it
+fn offset_in(text: &str, part: &str) -> usize {
+    part.as_ptr() as usize - text.as_ptr() as usize
+}
+
+// Where a `// ...` or `/* ... */` comment starting at `i` ends: at its
+// newline, after its `*/`, or at the end of text when it isn't closed
+fn comment_end(text: &str, i: usize) -> Option<usize> {
+    let rest = &text[i..];
+    if rest.starts_with("//") {
+        Some(rest.find('\n').map_or(text.len(), |n| i + n))
+    } else {
+        let body = rest.strip_prefix("/*")?;
+        Some(body.find("*/").map_or(text.len(), |n| i + 2 + n + 2))
+    }
+}
+
+// Every comment outside a quoted string, scanned like block_end does
+fn comment_spans(text: &str) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    let mut spans = Vec::new();
+    let mut in_string = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if in_string => i += 1,
+            b'"' => in_string = !in_string,
+            b'/' if !in_string => {
+                if let Some(end) = comment_end(text, i) {
+                    spans.push(i..end);
+                    i = end;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    spans
+}
+
+// Finds the closing brace of a block whose body starts at `start`, ignoring
+// braces and quotes inside comments, and braces inside quoted strings like
+// text:t="{placeholder}"
+fn block_end(text: &str, start: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 1;
+    let mut in_string = false;
+    let mut i = start;
+
+    // Braces, quotes and slashes are ASCII, so scanning bytes never splits a multibyte character
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if in_string => i += 1,
+            b'"' => in_string = !in_string,
+            _ if in_string => {}
+            b'/' => {
+                if let Some(end) = comment_end(text, i) {
+                    i = end;
+                    continue;
+                }
+            }
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// Whether every brace opened in text is closed again, for --repl to know a
+// snippet is complete. A stray `}` outside any block is skipped, as parsing
+// never looks at it either.
+pub fn is_balanced(text: &str) -> bool {
+    let padded = format!("{}}}", text);
+    let mut start = 0;
+    while let Some(end) = block_end(&padded, start) {
+        if end == text.len() {
+            return true;
+        }
+        start = end + 1;
+    }
+    false
+}
+
+// What was found before an unterminated block, and that block. It runs to the
+// end of the text, so nothing after it is lost: in a truncated file it is the
+// last one.
+type Partial<T> = (Vec<T>, Option<Unterminated<T>>);
+
+// A block without its closing brace: its start and body, running to the end of the text
+struct Unterminated<T> {
+    block: T,
+    error: anyhow::Error,
+}
+
+fn complete<T>((found, unterminated): Partial<T>) -> Result<Vec<T>> {
+    match unterminated {
+        Some(unterminated) => Err(unterminated.error),
+        None => Ok(found),
+    }
+}
+
+// Each block as the whole `name { ... }` text and its body. Scanning starts
+// at each opening match, so text between blocks, stray `}` included, is skipped,
+// and so is a match inside a comment.
+// `base` is where text starts in the whole input, for error offsets
+fn find_blocks<'a>(text: &'a str, re: &Regex, name: &str, base: usize) -> Partial<(&'a str, &'a str)> {
+    let mut blocks = Vec::new();
+    let comments = comment_spans(text);
+    let mut from = 0;
+
+    while let Some(mat) = re.find_at(text, from) {
+        if let Some(comment) = comments.iter().find(|comment| comment.contains(&mat.start())) {
+            from = comment.end;
+            continue;
+        }
+        let Some(end) = block_end(text, mat.end()) else {
+            let error = error_at(
+                base + mat.start(),
+                format!("Unterminated block `{}` opened at offset {}", name, base + mat.start()),
+            );
+            let block = (&text[mat.start()..], &text[mat.end()..]);
+            return (blocks, Some(Unterminated { block, error }));
+        };
+        blocks.push((&text[mat.start()..=end], &text[mat.end()..end]));
+        from = end + 1;
+    }
+    (blocks, None)
+}
+
+// The blocks shapes are read from: DRAW_BLOCKS, then the --block-alias names
+fn block_names(opts: &Options) -> impl Iterator<Item = &str> {
+    DRAW_BLOCKS
+        .iter()
+        .map(|(_, block_name)| *block_name)
+        .chain(opts.block_aliases.keys().map(String::as_str))
+}
+
+// Bodies of every `block_name { ... }`, however deeply nested in other blocks;
+// like entries, `DrawLines` or `DRAWLINES` count as `drawLines`
+fn extract_blocks<'a>(text: &'a str, block_name: &str) -> Result<Vec<&'a str>> {
+    complete(blocks_partial(text, block_name))
+}
+
+// As extract_blocks, but the body of an unterminated block is kept too
+fn blocks_partial<'a>(text: &'a str, block_name: &str) -> Partial<&'a str> {
+    let pattern = format!(r"(?mi)\b{}[\s\n]*\{{", regex::escape(block_name));
+    let (blocks, unterminated) = find_blocks(text, &Regex::new(&pattern).unwrap(), block_name, 0);
+    let unterminated = unterminated.map(|Unterminated { block: (_, body), error }| Unterminated { block: body, error });
+    (blocks.into_iter().map(|(_, body)| body).collect(), unterminated)
+}
+
+// Every `line{...}`-style entry and its body, matching the entry name in any case
+fn extract_entries<'a>(text: &'a str, entry_name: &str, base: usize) -> Partial<(&'a str, &'a str)> {
+    let pattern = format!(r"(?i)\b{}\s*\{{", regex::escape(entry_name));
+    find_blocks(text, &Regex::new(&pattern).unwrap(), entry_name, base)
+}
+
+// Entries of one kind across every draw block of text, in block order
+fn entries_in<'a>(text: &str, blocks: &[&'a str], entry_name: &str) -> Result<Vec<(&'a str, &'a str)>> {
+    complete(entries_partial(text, blocks, entry_name))
+}
+
+// As entries_in, keeping every complete entry; an unterminated one ends only its own block
+fn entries_partial<'a>(text: &str, blocks: &[&'a str], entry_name: &str) -> Partial<(&'a str, &'a str)> {
+    let mut entries = Vec::new();
+    let mut first = None;
+    for block in blocks {
+        let (found, unterminated) = extract_entries(block, entry_name, offset_in(text, block));
+        entries.extend(found);
+        first = first.or(unterminated);
+    }
+    (entries, first)
+}
+
+// Viewport width and height that relative units resolve against
+type Viewport = Option<(f64, f64)>;
+
+// Splits "x, y, ..." into numbers, tolerating spaces around every element;
+// `offset` is where text starts, so errors point at the bad element
+fn parse_coords(text: &str, offset: usize, viewport: Viewport) -> Result<Vec<f64>> {
+    let mut start = offset;
+    text.split(',')
+        .map(|s| {
+            let at = start + (s.len() - s.trim_start().len());
+            start += s.len() + 1;
+            parse_value(s.trim(), "coordinate", at, viewport)
+        })
+        .collect()
+}
+
+fn parse_number(text: &str, what: &str, offset: usize, viewport: Viewport) -> Result<f64> {
+    parse_value(text.trim(), what, offset, viewport)
+}
+
+// Plain numbers are absolute. A sw/sh (screen) or pw/ph (parent) suffix makes
+// them a percentage of the --viewport width or height; with no parent sizes
+// in a BLK file, the viewport stands in for the parent too.
+fn parse_value(text: &str, what: &str, offset: usize, viewport: Viewport) -> Result<f64> {
+    let invalid = || error_at(offset, format!("Invalid {}: {:?}", what, text));
+    let unit = text
+        .len()
+        .checked_sub(2)
+        .and_then(|split| text.get(split..))
+        .filter(|unit| ["sw", "sh", "pw", "ph"].iter().any(|known| unit.eq_ignore_ascii_case(known)));
+    let Some(unit) = unit else {
+        return parse_f64(text).ok_or_else(invalid);
+    };
+
+    let amount = parse_f64(&text[..text.len() - 2]).ok_or_else(invalid)?;
+    let (width, height) =
+        viewport.ok_or_else(|| error_at(offset, format!("{:?} is in relative units, which need --viewport", text)))?;
+    let size = if unit.ends_with(['w', 'W']) { width } else { height };
+    Ok(amount / 100.0 * size)
+}
+
+// Decimal, or a hex integer like 0x0A (optionally negative)
+fn parse_f64(text: &str) -> Option<f64> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, text),
+    };
+    match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        // from_str_radix would also take a second sign, as in "0x-1"
+        Some(hex) if hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            i64::from_str_radix(hex, 16).ok().map(|n| sign * n as f64)
+        }
+        Some(_) => None,
+        None => text.parse().ok(),
+    }
+}
+
+// One `key:type=value;` attribute, with the value's offset in the whole input
+struct Attr<'a> {
+    key: &'a str,
+    typ: &'a str,
+    value: &'a str,
+    offset: usize,
+    // Whether any parser looked it up; the rest end up in `extra`
+    used: Cell<bool>,
+}
+
+// The top level of an entry body: its attributes and nested `key{...}` blocks
+struct Entry<'a> {
+    offset: usize,
+    viewport: Viewport,
+    attrs: Vec<Attr<'a>>,
+    blocks: Vec<(&'a str, &'a str, usize)>,
+}
+
+impl<'a> Entry<'a> {
+    // `offset` is where body starts in the whole input
+    fn parse(body: &'a str, offset: usize, viewport: Viewport) -> Result<Self> {
+        let bytes = body.as_bytes();
+        let mut entry = Entry {
+            offset,
+            viewport,
+            attrs: Vec::new(),
+            blocks: Vec::new(),
+        };
+        let mut i = 0;
+
+        while i < bytes.len() {
+            if bytes[i].is_ascii_whitespace() || bytes[i] == b';' {
+                i += 1;
+                continue;
+            }
+            if let Some(end) = comment_end(body, i) {
+                i = end;
+                continue;
+            }
+
+            let key_start = i;
+            while i < bytes.len() && !matches!(bytes[i], b':' | b'{' | b';') && !bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            let key = &body[key_start..i];
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+
+            match bytes.get(i) {
+                Some(b'{') => {
+                    let end = block_end(body, i + 1)
+                        .ok_or_else(|| error_at(offset + key_start, format!("Unterminated block `{}`", key)))?;
+                    entry.blocks.push((key, &body[i + 1..end], offset + i + 1));
+                    i = end + 1;
+                }
+                Some(b':') => {
+                    // The type tag runs up to '=', the value up to an unquoted ';'
+                    let eq = body[i..]
+                        .find('=')
+                        .ok_or_else(|| error_at(offset + key_start, format!("Attribute `{}` has no value", key)))?;
+                    let typ = body[i + 1..i + eq].trim();
+                    i += eq + 1;
+                    let value_start = i;
+                    let mut in_string = false;
+                    while i < bytes.len() && (in_string || bytes[i] != b';') {
+                        match bytes[i] {
+                            b'\\' if in_string => i += 1,
+                            b'"' => in_string = !in_string,
+                            _ => {}
+                        }
+                        i += 1;
+                    }
+                    let value = &body[value_start..i.min(bytes.len())];
+                    entry.attrs.push(Attr {
+                        key,
+                        typ,
+                        value: value.trim(),
+                        offset: offset + value_start + (value.len() - value.trim_start().len()),
+                        used: Cell::new(false),
+                    });
+                }
+                _ => {
+                    return Err(error_at(
+                        offset + key_start,
+                        format!("Expected `key:type=value` near {:?}", key),
+                    ))
+                }
+            }
+        }
+
+        Ok(entry)
+    }
+
+    fn attr(&self, key: &str) -> Option<&Attr<'a>> {
+        let attr = self.attrs.iter().find(|attr| attr.key.eq_ignore_ascii_case(key))?;
+        attr.used.set(true);
+        Some(attr)
+    }
+
+    // Attributes nothing looked up, as `key:type` to the value (text without its quotes)
+    fn unused(&self) -> BTreeMap<String, String> {
+        self.attrs
+            .iter()
+            .filter(|attr| !attr.used.get())
+            .map(|attr| (format!("{}:{}", attr.key, attr.typ), unquote(attr.value).to_string()))
+            .collect()
+    }
+
+    fn block(&self, key: &str) -> Result<Option<Entry<'a>>> {
+        self.blocks
+            .iter()
+            .find(|(name, _, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, body, offset)| Entry::parse(body, *offset, self.viewport))
+            .transpose()
+    }
+
+    fn number(&self, key: &str, what: &str) -> Result<Option<f64>> {
+        self.attr(key)
+            .map(|attr| parse_number(attr.value, what, attr.offset, self.viewport))
+            .transpose()
+    }
+
+    // `key:b=true` or `key:b=false`
+    fn flag(&self, key: &str, what: &str) -> Result<Option<bool>> {
+        self.attr(key)
+            .map(|attr| match attr.value {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                value => Err(error_at(attr.offset, format!("Invalid {}: {:?}", what, value))),
+            })
+            .transpose()
+    }
+
+    // Either `key:p2=x,y` or a `key{x:r=..; y:r=..;}` sub-block
+    fn point(&self, key: &str, what: &str) -> Result<Option<Point>> {
+        if let Some(attr) = self.attr(key) {
+            let coords = parse_coords(attr.value, attr.offset, self.viewport)?;
+            if coords.len() != 2 {
+                return Err(error_at(attr.offset, format!("Invalid {}: {}", what, attr.value)));
+            }
+            return Ok(Some(Point {
+                x: coords[0],
+                y: coords[1],
+                z: None,
+            }));
+        }
+
+        let Some(block) = self.block(key)? else {
+            return Ok(None);
+        };
+        match (block.number("x", what)?, block.number("y", what)?) {
+            (Some(x), Some(y)) => Ok(Some(Point {
+                x,
+                y,
+                z: block.number("z", what)?,
+            })),
+            _ => Err(error_at(block.offset, format!("Invalid {}: needs both x and y", what))),
+        }
+    }
+
+    fn required_point(&self, key: &str, what: &str) -> Result<Point> {
+        self.point(key, what)?
+            .ok_or_else(|| self.missing(&format!("{} (`{}`)", what, key)))
+    }
+
+    fn required_number(&self, key: &str, what: &str) -> Result<f64> {
+        self.number(key, what)?
+            .ok_or_else(|| self.missing(&format!("{} (`{}`)", what, key)))
+    }
+
+    // Missing attributes have no token of their own, so point at the entry
+    fn missing(&self, what: &str) -> anyhow::Error {
+        error_at(self.offset, format!("Missing {}", what))
+    }
+}
+
+// Attributes shared by every kind of entry
+fn parse_attrs(entry: &Entry, opts: &Options) -> Result<Attrs> {
+    let layer = entry
+        .attr("layer")
+        .or_else(|| entry.attr("order"))
+        .map(|attr| {
+            attr.value
+                .parse()
+                .map_err(|_| error_at(attr.offset, format!("Invalid layer: {:?}", attr.value)))
+        })
+        .transpose()?;
+    let hidden = entry.flag("hidden", "hidden flag")?;
+    // Out-of-range opacity is still usable, so clamp it rather than fail
+    let opacity = entry.number("opacity", "opacity")?.map(|opacity| {
+        let clamped = opacity.clamp(0.0, 1.0);
+        if clamped != opacity {
+            eprintln!("Warning: opacity {} is outside 0..1, clamped to {}", opacity, clamped);
+        }
+        clamped
+    });
+
+    // --group-by-attr reads any attribute, text ones without their quotes
+    let group = opts
+        .group_by_attr
+        .as_deref()
+        .and_then(|key| entry.attr(key))
+        .map(|attr| unquote(attr.value).to_string());
+
+    Ok(Attrs {
+        layer,
+        hidden,
+        opacity,
+        group,
+        // Filled in by parse_entry, once every other attribute was looked up
+        extra: BTreeMap::new(),
+        raw: None,
+        centroid: None,
+        uuid: None,
+    })
+}
+
+fn unquote(value: &str) -> &str {
+    value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value)
+}
+
+// Entry names in the order parse_shapes yields them, before any shapes.toml ones
+pub const ENTRY_KINDS: &[&str] = &["line", "quad", "quads", "rect", "arc", "polygon", "bezier"];
+
+// Either `line:p4=x1,y1,x2,y2` or separate `x1:r=..; y1:r=..; x2:r=..; y2:r=..;`
+fn line_coords(entry: &Entry) -> Result<Vec<f64>> {
+    if let Some(coords_attr) = entry.attr("line") {
+        let coords = parse_coords(coords_attr.value, coords_attr.offset, entry.viewport)?;
+        if coords.len() != 4 {
+            return Err(error_at(
+                coords_attr.offset,
+                format!("Invalid line coordinates: {}", coords_attr.value),
+            ));
+        }
+        return Ok(coords);
+    }
+
+    let scalars = ["x1", "y1", "x2", "y2"]
+        .iter()
+        .map(|key| entry.number(key, "line coordinate"))
+        .collect::<Result<Vec<_>>>()?;
+    if scalars.iter().all(Option::is_some) {
+        Ok(scalars.into_iter().flatten().collect())
+    } else {
+        Err(entry.missing("line coordinates (`line:p4` or x1/y1/x2/y2)"))
+    }
+}
+
+// `color:c=r,g,b` or `r,g,b,a`, each 0..255; alpha defaults to opaque
+fn parse_color(entry: &Entry) -> Result<Option<[u8; 4]>> {
+    let Some(attr) = entry.attr("color") else {
+        return Ok(None);
+    };
+    let invalid = || error_at(attr.offset, format!("Invalid line color: {:?}", attr.value));
+    let channels = attr
+        .value
+        .split(',')
+        .map(|channel| channel.trim().parse::<u8>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>>>()?;
+    match channels[..] {
+        [r, g, b] => Ok(Some([r, g, b, 255])),
+        [r, g, b, a] => Ok(Some([r, g, b, a])),
+        _ => Err(invalid()),
+    }
+}
+
+// Attributes may come in any order, mixed with the coordinates
+fn parse_line(entry: &Entry, opts: &Options) -> Result<Shape> {
+    let coords = line_coords(entry)?;
+
+    Ok(Shape::Line {
+        name: String::new(),
+        typ: opts.type_name("line"),
+        start: Point {
+            x: coords[0],
+            y: coords[1],
+            z: None,
+        },
+        end: Point {
+            x: coords[2],
+            y: coords[3],
+            z: None,
+        },
+        color: parse_color(entry)?,
+        width: entry.number("width", "line width")?,
+        moves: entry.flag("move", "move flag")?,
+        selected: false,
+        attrs: parse_attrs(entry, opts)?,
+    })
+}
+
+fn parse_quad(entry: &Entry, opts: &Options) -> Result<Shape> {
+    let corners = [
+        entry.required_point("tl", "quad corner")?,
+        entry.required_point("tr", "quad corner")?,
+        entry.required_point("br", "quad corner")?,
+        entry.required_point("bl", "quad corner")?,
+    ];
+    quad(corners, entry, opts)
+}
+
+// `rect{lt:p2=x,y; rb:p2=x,y;}`, the left-top and right-bottom corners of an
+// axis-aligned rectangle, becomes the quad through all four
+fn parse_rect(entry: &Entry, opts: &Options) -> Result<Shape> {
+    let lt = entry.required_point("lt", "rect corner")?;
+    let rb = entry.required_point("rb", "rect corner")?;
+    // A depth given on just one corner can't be spread over the others
+    let z = if lt.z == rb.z { lt.z } else { None };
+    let corners = [
+        Point { x: lt.x, y: lt.y, z },
+        Point { x: rb.x, y: lt.y, z },
+        Point { x: rb.x, y: rb.y, z },
+        Point { x: lt.x, y: rb.y, z },
+    ];
+    quad(corners, entry, opts)
+}
+
+// Corners in tl, tr, br, bl order, with the entry's rotation and shared attributes
+fn quad(mut corners: [Point; 4], entry: &Entry, opts: &Options) -> Result<Shape> {
+    let mut rotation = entry.number("rotation", "quad rotation")?;
+    if opts.apply_transform && let Some(degrees) = rotation.take() {
+        rotate_around_centroid(&mut corners, degrees);
+    }
+    let [pos1, pos2, pos3, pos4] = corners;
+
+    Ok(Shape::Quad {
+        name: String::new(),
+        typ: opts.type_name("quad"),
+        pos1,
+        pos2,
+        pos3,
+        pos4,
+        rotation,
+        selected: false,
+        attrs: parse_attrs(entry, opts)?,
+    })
+}
+
+// Bulk quads, `quads{p2:array=[[x,y],...]}` with four corners per quad
+fn parse_bulk_quads(entry: &Entry, opts: &Options) -> Result<Vec<Shape>> {
+    let array = entry
+        .attr("p2")
+        .ok_or_else(|| entry.missing("bulk quad corners (`p2:array`)"))?;
+    let points: Vec<[f64; 2]> = serde_json::from_str(array.value)
+        .map_err(|_| error_at(array.offset, format!("Invalid point array: {}", array.value)))?;
+    if !points.len().is_multiple_of(4) {
+        return Err(error_at(
+            array.offset,
+            format!("Invalid bulk quads: {} points is not a multiple of 4", points.len()),
+        ));
+    }
+
+    points
+        .chunks(4)
+        .map(|corners| {
+            let [pos1, pos2, pos3, pos4] = std::array::from_fn(|i| Point {
+                x: corners[i][0],
+                y: corners[i][1],
+                z: None,
+            });
+            Ok(Shape::Quad {
+                name: String::new(),
+                typ: opts.type_name("quad"),
+                pos1,
+                pos2,
+                pos3,
+                pos4,
+                rotation: None,
+                selected: false,
+                attrs: parse_attrs(entry, opts)?,
+            })
+        })
+        .collect()
+}
+
+fn parse_arc(entry: &Entry, opts: &Options) -> Result<Shape> {
+    Ok(Shape::Arc {
+        name: String::new(),
+        typ: opts.type_name("arc"),
+        center: entry.required_point("center", "arc center")?,
+        radius: entry.required_number("radius", "arc radius")?,
+        start_angle: entry.required_number("start", "arc start angle")?,
+        end_angle: entry.required_number("end", "arc end angle")?,
+        selected: false,
+        attrs: parse_attrs(entry, opts)?,
+    })
+}
+
+// `points:p2=x1,y1,x2,y2,...` with any number of vertices
+fn parse_polygon(entry: &Entry, opts: &Options) -> Result<Shape> {
+    let points_attr = entry
+        .attr("points")
+        .ok_or_else(|| entry.missing("polygon vertices (`points:p2`)"))?;
+    let coords = parse_coords(points_attr.value, points_attr.offset, entry.viewport)?;
+    if coords.is_empty() || !coords.len().is_multiple_of(2) {
+        return Err(error_at(
+            points_attr.offset,
+            format!("Invalid polygon vertices: {} (expected x,y pairs)", points_attr.value),
+        ));
+    }
+
+    Ok(Shape::Polygon {
+        name: String::new(),
+        typ: opts.type_name("polygon"),
+        points: coords
+            .chunks(2)
+            .map(|xy| Point {
+                x: xy[0],
+                y: xy[1],
+                z: None,
+            })
+            .collect(),
+        selected: false,
+        attrs: parse_attrs(entry, opts)?,
+    })
+}
+
+fn parse_bezier(entry: &Entry, opts: &Options) -> Result<Shape> {
+    Ok(Shape::Bezier {
+        name: String::new(),
+        typ: opts.type_name("bezier"),
+        p0: entry.required_point("p0", "curve point")?,
+        p1: entry.required_point("p1", "curve point")?,
+        p2: entry.required_point("p2", "curve point")?,
+        p3: entry.required_point("p3", "curve point")?,
+        selected: false,
+        attrs: parse_attrs(entry, opts)?,
+    })
+}
+
+// A shapes.toml shape: every field it defines, read as a point or a number
+fn parse_custom(entry: &Entry, shape: &CustomShape, opts: &Options) -> Result<Shape> {
+    let mut fields = BTreeMap::new();
+    for (key, kind) in &shape.fields {
+        let what = format!("{} {}", shape.entry, key);
+        let field = match kind {
+            FieldKind::Point => Field::Point(entry.required_point(key, &what)?),
+            FieldKind::Scalar => Field::Scalar(entry.required_number(key, &what)?),
+        };
+        fields.insert(key.clone(), field);
+    }
+
+    Ok(Shape::Custom {
+        name: String::new(),
+        typ: shape.type_name().to_string(),
+        fields,
+        selected: false,
+        attrs: parse_attrs(entry, opts)?,
+    })
+}
+
+// An entry name from ENTRY_KINDS, or a shapes.toml definition
+#[derive(Clone, Copy)]
+enum Kind<'a> {
+    Builtin(&'static str),
+    Custom(&'a CustomShape),
+}
+
+// One entry of the given kind; only bulk entries make more than one shape
+fn parse_entry(kind: Kind, text: &str, (raw, body): (&str, &str), opts: &Options) -> Result<Vec<Shape>> {
+    let entry = Entry::parse(body, offset_in(text, body), opts.viewport)?;
+    let mut shapes = match kind {
+        Kind::Custom(shape) => vec![parse_custom(&entry, shape, opts)?],
+        Kind::Builtin("line") => vec![parse_line(&entry, opts)?],
+        Kind::Builtin("quad") => vec![parse_quad(&entry, opts)?],
+        Kind::Builtin("quads") => parse_bulk_quads(&entry, opts)?,
+        Kind::Builtin("rect") => vec![parse_rect(&entry, opts)?],
+        Kind::Builtin("polygon") => vec![parse_polygon(&entry, opts)?],
+        Kind::Builtin("bezier") => vec![parse_bezier(&entry, opts)?],
+        Kind::Builtin(_) => vec![parse_arc(&entry, opts)?],
+    };
+    // An entry-level depth goes to every point without its own
+    let z = entry.number("z", "depth")?;
+    let extra = entry.unused();
+    for shape in &mut shapes {
+        for point in shape.points_mut() {
+            point.z = point.z.or(z);
+        }
+        shape.attrs_mut().extra = extra.clone();
+        if opts.with_raw {
+            shape.attrs_mut().raw = Some(raw.to_string());
+        }
+    }
+    Ok(shapes)
+}
+
+// Yields shapes one at a time: only entry positions are found up front,
+// one kind at a time, and each entry is parsed when reached
+pub struct Shapes<'a> {
+    text: &'a str,
+    opts: &'a Options,
+    blocks: Vec<&'a str>,
+    kinds: std::slice::Iter<'static, &'static str>,
+    custom: std::slice::Iter<'a, CustomShape>,
+    kind: Kind<'a>,
+    entries: std::vec::IntoIter<(&'a str, &'a str)>,
+    pending: std::vec::IntoIter<Shape>,
+    error: Option<anyhow::Error>,
+    // An unterminated entry, yielded after the complete ones of its kind
+    entry_error: Option<anyhow::Error>,
+    // An unterminated draw block, yielded once everything before it is
+    truncated: Option<anyhow::Error>,
+    done: bool,
+    flip_y: bool,
+    // Shapes yielded so far, for --max-shapes
+    count: usize,
+}
+
+impl Iterator for Shapes<'_> {
+    type Item = Result<Shape>;
+
+    fn next(&mut self) -> Option<Result<Shape>> {
+        loop {
+            if let Some(e) = self.error.take() {
+                self.done = true;
+                return Some(Err(e));
+            }
+            if self.done {
+                return None;
+            }
+            if let Some(mut shape) = self.pending.next() {
+                self.count += 1;
+                // Stops before the rest of a giant input is parsed
+                if let Some(max) = self.opts.max_shapes
+                    && self.count > max
+                {
+                    self.error = Some(TooManyShapes(max).into());
+                    continue;
+                }
+                if self.flip_y {
+                    shape.flip_y();
+                }
+                return Some(Ok(shape));
+            }
+            if let Some(entry) = self.entries.next() {
+                match profile::time("parse entries", || parse_entry(self.kind, self.text, entry, self.opts)) {
+                    Ok(shapes) => self.pending = shapes.into_iter(),
+                    // Only this entry is lost; the next call goes on to the one after it
+                    Err(e) => return Some(Err(e)),
+                }
+                continue;
+            }
+            if let Some(e) = self.entry_error.take() {
+                return Some(Err(e));
+            }
+
+            let (entries, unterminated) = if let Some(kind) = self.kinds.next() {
+                self.kind = Kind::Builtin(kind);
+                let (entries, entry) = profile::time("find entries", || entries_partial(self.text, &self.blocks, kind));
+                (entries, entry.map(|entry| entry.error))
+            } else if let Some(shape) = self.custom.next() {
+                // Custom entries are only looked for in their own block
+                self.kind = Kind::Custom(shape);
+                profile::time("find entries", || {
+                    let (mut blocks, block) = blocks_partial(self.text, &shape.block);
+                    blocks.extend(block.as_ref().map(|block| block.block));
+                    let (entries, entry) = entries_partial(self.text, &blocks, &shape.entry);
+                    (entries, entry.map(|entry| entry.error).or(block.map(|block| block.error)))
+                })
+            } else {
+                self.done = true;
+                return self.truncated.take().map(Err);
+            };
+            self.entries = entries.into_iter();
+            self.entry_error = unterminated;
+        }
+    }
+}
+
+// A bad entry comes out as an error in its place and the rest still follow.
+// So do the complete entries of a truncated file, its error coming last.
+pub fn parse_shapes<'a>(text: &'a str, opts: &'a Options) -> Shapes<'a> {
+    let mut blocks = Vec::new();
+    let mut truncated = None;
+    profile::time("find blocks", || {
+        for block_name in block_names(opts) {
+            let (found, unterminated) = blocks_partial(text, block_name);
+            blocks.extend(found);
+            if let Some(Unterminated { block, error }) = unterminated {
+                blocks.push(block);
+                truncated.get_or_insert(error);
+            }
+        }
+    });
+
+    Shapes {
+        text,
+        opts,
+        blocks,
+        kinds: ENTRY_KINDS.iter(),
+        custom: opts.custom_shapes.iter(),
+        kind: Kind::Builtin(""),
+        entries: Vec::new().into_iter(),
+        pending: Vec::new().into_iter(),
+        error: None,
+        entry_error: None,
+        truncated,
+        done: false,
+        flip_y: coord_system(text, opts) == CoordSystem::Screen,
+        count: 0,
+    }
+}
+
+// --count-only: shapes per kind, from the entries parse_shapes would find
+// without parsing any of them. A bulk `quads` entry counts its `[x,y]` corners
+// in fours; filters such as --select or --skip-hidden don't apply.
+pub fn count_entries(text: &str, opts: &Options) -> Result<ShapeCounts> {
+    let mut blocks = Vec::new();
+    for block_name in block_names(opts) {
+        blocks.extend(extract_blocks(text, block_name)?);
+    }
+    let mut counts: ShapeCounts = SHAPE_TYPES.iter().map(|kind| (*kind, 0)).collect();
+    for kind in ENTRY_KINDS {
+        let entries = entries_in(text, &blocks, kind)?;
+        let (kind, count) = match *kind {
+            // The corners sit inside one outer pair of brackets
+            "quads" => (
+                "quad",
+                entries
+                    .iter()
+                    .map(|(_, body)| body.matches('[').count().saturating_sub(1) / 4)
+                    .sum(),
+            ),
+            "rect" => ("quad", entries.len()),
+            kind => (kind, entries.len()),
+        };
+        *counts.entry(kind).or_default() += count;
+    }
+    for shape in &opts.custom_shapes {
+        let blocks = extract_blocks(text, &shape.block)?;
+        *counts.entry("custom").or_default() += entries_in(text, &blocks, &shape.entry)?.len();
+    }
+    Ok(counts)
+}
+
+// --coord-system, else a `coordSystem:t="screen"` declaration anywhere in
+// the file, else y already points up
+fn coord_system(text: &str, opts: &Options) -> CoordSystem {
+    if let Some(system) = opts.coord_system {
+        return system;
+    }
+    // Spaced like any other attribute, e.g. `coordSystem : t = "screen"`
+    let declaration = Regex::new(r#"(?i)\bcoordSystem\s*:\s*t\s*=\s*"([^"]*)""#).unwrap();
+    let Some(name) = declaration.captures(text).map(|caps| caps[1].to_string()) else {
+        return CoordSystem::Cartesian;
+    };
+    CoordSystem::from_name(&name).unwrap_or_else(|| {
+        eprintln!("Warning: unknown coordSystem {:?}, leaving coordinates as they are", name);
+        CoordSystem::Cartesian
+    })
+}
+
+// A `units:t="mm"` declaration anywhere in the file, for --with-meta
+pub fn declared_units(text: &str) -> Option<String> {
+    let declaration = Regex::new(r#"(?i)\bunits\s*:\s*t\s*=\s*"([^"]*)""#).unwrap();
+    declaration.captures(text).map(|caps| caps[1].to_string())
+}
+
+// One `// ...` or `/* ... */` comment, for --keep-comments
+#[derive(Serialize)]
+pub struct Comment {
+    // Which input it came from, when several are merged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    // Where it starts, counting from 1
+    pub line: usize,
+    pub text: String,
+}
+
+// Every comment outside a quoted value, without its markers; a `//` inside
+// `url:t="http://..."` is part of the value. The spans are the ones block and
+// entry scanning skip, so a commented-out block is listed here and nowhere else.
+pub fn comments(text: &str) -> Vec<Comment> {
+    let mut line = 1;
+    let mut counted = 0;
+    comment_spans(text)
+        .into_iter()
+        .map(|span| {
+            line += text[counted..span.start].matches('\n').count();
+            counted = span.start;
+            let body = &text[span];
+            let body = body.strip_prefix("//").unwrap_or(body);
+            let body = body.strip_prefix("/*").map_or(body, |body| body.strip_suffix("*/").unwrap_or(body));
+            Comment {
+                source: None,
+                line,
+                text: body.trim().to_string(),
+            }
+        })
+        .collect()
+}
+
+pub fn parse_input(text: &str, opts: &Options) -> Result<Vec<Shape>> {
+    if !opts.allow_partial {
+        return parse_shapes(text, opts).collect();
+    }
+    let mut shapes = Vec::new();
+    for shape in parse_shapes(text, opts) {
+        match shape {
+            Ok(shape) => shapes.push(shape),
+            Err(e) if e.is::<TooManyShapes>() => return Err(e),
+            Err(e) => {
+                report_partial(&e);
+            }
+        }
+    }
+    Ok(shapes)
+}
+
+// --allow-partial: the error is reported, and the entry it came from left out
+// (for a truncated file, the unfinished entry at its end)
+pub fn report_partial(e: &anyhow::Error) {
+    eprintln!("Error: {}", e);
+    eprintln!("Warning: leaving out what it spoils and keeping every other shape (--allow-partial)");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Vec<Shape> {
+        parse_input(text, &Options::default()).unwrap()
+    }
+
+    // Each shape as its kind and the x,y of its points
+    fn summary(shapes: &[Shape]) -> Vec<(&'static str, Vec<(f64, f64)>)> {
+        shapes
+            .iter()
+            .map(|shape| (shape.kind(), shape.points().iter().map(|p| (p.x, p.y)).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn interleaved_lines_and_quads_are_all_kept() {
+        let text = "drawLines{ line{line:p4=0,0,1,1;} }\n\
+                    drawQuads{ quad{tl:p2=0,0;tr:p2=1,0;br:p2=1,1;bl:p2=0,1;} }\n\
+                    drawLines{ line{line:p4=2,2,3,3;} }\n\
+                    drawQuads{ quad{tl:p2=5,5;tr:p2=6,5;br:p2=6,6;bl:p2=5,6;} }";
+        // Lines come first, then quads, each kind in file order
+        assert_eq!(
+            summary(&parse(text)),
+            vec![
+                ("line", vec![(0.0, 0.0), (1.0, 1.0)]),
+                ("line", vec![(2.0, 2.0), (3.0, 3.0)]),
+                ("quad", vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]),
+                ("quad", vec![(5.0, 5.0), (6.0, 5.0), (6.0, 6.0), (5.0, 6.0)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn quad_corners_tolerate_spaces_around_every_element() {
+        let text = "drawQuads{ quad{ tl:p2 = 10 , 20 ; tr:p2= 30 ,20; br : p2 =30 , 40 ;bl:p2=  10,  40  ;} }";
+        assert_eq!(
+            summary(&parse(text)),
+            vec![("quad", vec![(10.0, 20.0), (30.0, 20.0), (30.0, 40.0), (10.0, 40.0)])]
+        );
+    }
+
+    #[test]
+    fn draw_blocks_are_found_inside_other_blocks() {
+        let text = "root{ hud{ drawLines{ line{line:p4=1,2,3,4;} } } }";
+        assert_eq!(extract_blocks(text, "drawLines").unwrap(), vec![" line{line:p4=1,2,3,4;} "]);
+        assert_eq!(summary(&parse(text)), vec![("line", vec![(1.0, 2.0), (3.0, 4.0)])]);
+    }
+
+    #[test]
+    fn comments_hide_quotes_braces_and_block_names() {
+        let text = "drawLines{\n  // the \"main line\n  line{line:p4=1,2,3,4;}\n  /* closes with } here */\n  line{line:p4=5,6,7,8;}\n}\n// drawLines{ line{line:p4=0,0,9,9;} }";
+        assert_eq!(
+            summary(&parse(text)),
+            vec![("line", vec![(1.0, 2.0), (3.0, 4.0)]), ("line", vec![(5.0, 6.0), (7.0, 8.0)])]
+        );
+        // Still a value, not a comment
+        let text = "drawLines{ line{line:p4=1,2,3,4; url:t=\"http://x/}\";} }";
+        assert_eq!(parse(text).len(), 1);
+    }
+
+    #[test]
+    fn multibyte_text_in_and_around_blocks() {
+        let text = "// Карта 🗺️ {\nhud{ подпись:t=\"Привет 🚀 {\"; drawLines{ line{line:p4=1,2,3,4; name:t=\"Линия 🚀\";} } }";
+        assert_eq!(
+            extract_blocks(text, "drawLines").unwrap(),
+            vec![" line{line:p4=1,2,3,4; name:t=\"Линия 🚀\";} "]
+        );
+        let shapes = parse(text);
+        assert_eq!(summary(&shapes), vec![("line", vec![(1.0, 2.0), (3.0, 4.0)])]);
+        assert_eq!(shapes[0].attrs().extra["name:t"], "Линия 🚀");
+
+        // Offsets count bytes, so they land on the bad value after the multibyte text
+        let text = "/* ёжик 🦔 */ drawLines{ line{line:p4=1,2,ы,4;} }";
+        let e = parse_input(text, &Options::default()).unwrap_err();
+        assert_eq!(e.downcast_ref::<ParseError>().unwrap().offset, text.find('ы').unwrap());
+        let text = "🦔 drawLines{ line{line:p4=1,2,3,4;}";
+        let e = parse_input(text, &Options::default()).unwrap_err();
+        assert_eq!(e.downcast_ref::<ParseError>().unwrap().offset, text.find('d').unwrap());
+    }
+
+    #[test]
+    fn block_and_entry_names_match_in_any_case() {
+        let text = "DRAWLINES{ LINE{line:p4=1,2,3,4;} } drawlines{ Line{line:p4=5,6,7,8;} } DrawQuads{ quad{tl:p2=0,0;tr:p2=1,0;br:p2=1,1;bl:p2=0,1;} }";
+        assert_eq!(extract_blocks(text, "drawLines").unwrap().len(), 2);
+        assert_eq!(
+            summary(&parse(text)).iter().map(|(kind, _)| *kind).collect::<Vec<_>>(),
+            vec!["line", "line", "quad"]
+        );
+    }
+
+    #[test]
+    fn flipping_a_zero_leaves_no_negative_zero() {
+        let shapes = parse(r#"coordSystem:t="screen" drawLines{ line{line:p4=0,0,1,-2;} }"#);
+        let ys: Vec<f64> = shapes[0].points().iter().map(|p| p.y).collect();
+        assert_eq!(ys, [0.0, 2.0]);
+        assert!(ys[0].is_sign_positive());
+    }
+
+    #[test]
+    fn a_stray_closing_brace_before_the_blocks_is_ignored() {
+        let text = "} }
+            drawLines{ line{line:p4=0,1,2,3;} }
+            drawQuads{ quad{tl:p2=0,0;tr:p2=1,0;br:p2=1,1;bl:p2=0,1;} }";
+        let shapes = parse(text);
+        assert_eq!(
+            summary(&shapes),
+            [
+                ("line", vec![(0.0, 1.0), (2.0, 3.0)]),
+                ("quad", vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn allow_partial_still_stops_at_max_shapes() {
+        let opts = Options {
+            max_shapes: Some(1),
+            allow_partial: true,
+            ..Options::default()
+        };
+        let e = parse_input("drawLines{ line{line:p4=0,1,2,3;} line{line:p4=4,5,6,7;} }", &opts).unwrap_err();
+        assert!(e.is::<TooManyShapes>());
+    }
+
+    #[test]
+    fn allow_partial_skips_only_the_bad_entry() {
+        let opts = Options {
+            allow_partial: true,
+            ..Options::default()
+        };
+        let text = "drawLines{ line{line:p4=0,1,2,3;} line{line:p4=oops;} }
+            drawQuads{ quad{tl:p2=0,0;tr:p2=1,0;br:p2=1,1;bl:p2=0,1;} }";
+        let shapes = parse_input(text, &opts).unwrap();
+        assert_eq!(shapes.iter().map(Shape::kind).collect::<Vec<_>>(), ["line", "quad"]);
+    }
+
+    #[test]
+    fn tabs_and_spaces_convert_alike() {
+        let spaced = "drawLines{\n    line{ line:p4 = 0, 1, 2, 3; width:r = 2; }\n}\n\
+            drawQuads{\n  quad{ tl:p2 = 0, 0; tr:p2 = 1, 0; br:p2 = 1, 1; bl:p2 = 0, 1; }\n}";
+        let tabbed = spaced.replace("    ", "\t").replace(' ', "\t");
+        assert!(tabbed.contains('\t') && !tabbed.contains(' '));
+        assert_eq!(parse(&tabbed), parse(spaced));
+        assert_eq!(parse(spaced).len(), 2);
+    }
+
+    #[test]
+    fn a_commented_out_block_is_a_comment_and_not_a_shape() {
+        let text = "drawLines{ line{line:p4=0,1,2,3;} }\n// drawLines{ line{line:p4=4,5,6,7;} }\n";
+        assert_eq!(summary(&parse(text)), vec![("line", vec![(0.0, 1.0), (2.0, 3.0)])]);
+        let comments = comments(text);
+        assert_eq!(comments.len(), 1);
+        assert_eq!((comments[0].line, comments[0].text.as_str()), (2, "drawLines{ line{line:p4=4,5,6,7;} }"));
+    }
+
+    #[test]
+    fn a_truncated_file_keeps_what_came_before_the_cut() {
+        let text = "drawLines{ line{line:p4=0,1,2,3;} line{line:p4=4,5,6,7;} }
+            drawQuads{ quad{tl:p2=0,0;tr:p2=1,0;br:p2=1,1;bl:p2=0,1;} quad{tl:p2=0,0;tr:p2=1,";
+        let opts = Options {
+            allow_partial: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            summary(&parse_input(text, &opts).unwrap()),
+            [
+                ("line", vec![(0.0, 1.0), (2.0, 3.0)]),
+                ("line", vec![(4.0, 5.0), (6.0, 7.0)]),
+                ("quad", vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]),
+            ]
+        );
+        // Each shape still comes out before the errors, which end the run without --allow-partial
+        let items: Vec<Result<Shape>> = parse_shapes(text, &Options::default()).collect();
+        assert_eq!(items.iter().filter(|item| item.is_ok()).count(), 3);
+        assert!(items[3..].iter().all(Result::is_err));
+        let e = parse_input(text, &Options::default()).unwrap_err();
+        assert!(e.to_string().starts_with("Unterminated block `quad`"));
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// --profile: time spent in each conversion phase, summed over every file
+// (and every thread) of the run, in the order the phases first ran
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PHASES: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn time<T>(phase: &'static str, f: impl FnOnce() -> T) -> T {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return f();
+    }
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+
+    let mut phases = PHASES.lock().unwrap_or_else(|e| e.into_inner());
+    match phases.iter_mut().find(|(name, _)| *name == phase) {
+        Some((_, total)) => *total += elapsed,
+        None => phases.push((phase, elapsed)),
+    }
+    result
+}
+
+// On stderr, so the output itself stays clean
+pub fn report() {
+    let phases = PHASES.lock().unwrap_or_else(|e| e.into_inner());
+    let width = phases.iter().map(|(name, _)| name.len()).fold("total".len(), usize::max);
+    for (name, total) in phases.iter() {
+        eprintln!("{:width$}  {:>10.3} ms", name, total.as_secs_f64() * 1000.0, width = width);
+    }
+    let total: Duration = phases.iter().map(|(_, total)| *total).sum();
+    eprintln!("{:width$}  {:>10.3} ms", "total", total.as_secs_f64() * 1000.0, width = width);
+}
@@ -0,0 +1,301 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    cli::Options,
+    custom::{CustomShape, FieldKind},
+    SHAPE_TYPES,
+};
+
+fn point() -> Value {
+    json!({
+        "type": "object",
+        "properties": { "x": { "type": "number" }, "y": { "type": "number" }, "z": { "type": "number" } },
+        "required": ["x", "y"],
+        "additionalProperties": false
+    })
+}
+
+// One shape kind: its own fields plus the shared ones every entry may carry
+fn shape(fields: &[(&str, Value)], optional: &[(&str, Value)]) -> Value {
+    let mut properties = Map::new();
+    properties.insert("name".into(), json!({ "type": "string" }));
+    properties.insert("type".into(), json!({ "type": "string" }));
+    // --strip-names leaves "name" out
+    let mut required = vec!["type", "selected"];
+    for (key, schema) in fields {
+        properties.insert(key.to_string(), schema.clone());
+        required.push(key);
+    }
+    for (key, schema) in optional {
+        properties.insert(key.to_string(), schema.clone());
+    }
+    properties.insert("selected".into(), json!({ "type": "boolean" }));
+    properties.insert("layer".into(), json!({ "type": "integer" }));
+    properties.insert("hidden".into(), json!({ "type": "boolean" }));
+    properties.insert("opacity".into(), json!({ "type": "number", "minimum": 0, "maximum": 1 }));
+    properties.insert(
+        "extra".into(),
+        json!({ "type": "object", "additionalProperties": { "type": "string" } }),
+    );
+    properties.insert("_raw".into(), json!({ "type": "string" }));
+    properties.insert("centroid".into(), point());
+    properties.insert("uuid".into(), json!({ "type": "string" }));
+
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false
+    })
+}
+
+// A shapes.toml definition, told apart from the others by its type
+fn custom(definition: &CustomShape) -> Value {
+    let fields: Vec<(&str, Value)> = definition
+        .fields
+        .iter()
+        .map(|(key, kind)| match kind {
+            FieldKind::Point => (key.as_str(), point()),
+            FieldKind::Scalar => (key.as_str(), json!({ "type": "number" })),
+        })
+        .collect();
+    let mut shape = shape(&fields, &[]);
+    shape["properties"]["type"] = json!({ "type": "string", "const": definition.type_name() });
+    shape
+}
+
+// JSON Schema (draft-07) for the converted output; custom shapes are
+// those of the given shapes.toml definitions
+pub fn schema(custom_shapes: &[CustomShape]) -> Value {
+    let number = || json!({ "type": "number" });
+    let mut shapes = vec![
+        shape(
+            &[("start", point()), ("end", point())],
+            &[
+                (
+                    "color",
+                    json!({ "type": "array", "items": { "type": "integer", "minimum": 0, "maximum": 255 } }),
+                ),
+                ("width", number()),
+                ("move", json!({ "type": "boolean" })),
+            ],
+        ),
+        shape(
+            &[("pos1", point()), ("pos2", point()), ("pos3", point()), ("pos4", point())],
+            &[("rotation", number())],
+        ),
+        shape(
+            &[("pos1", point()), ("pos2", point()), ("pos3", point())],
+            &[("rotation", number())],
+        ),
+        shape(
+            &[
+                ("center", point()),
+                ("radius", number()),
+                ("start_angle", number()),
+                ("end_angle", number()),
+            ],
+            &[],
+        ),
+        shape(&[("points", json!({ "type": "array", "items": point() }))], &[]),
+        shape(&[("p0", point()), ("p1", point()), ("p2", point()), ("p3", point())], &[]),
+    ];
+    // An empty anyOf would match nothing, so it's only there with a definition
+    if !custom_shapes.is_empty() {
+        shapes.push(json!({ "anyOf": custom_shapes.iter().map(custom).collect::<Vec<_>>() }));
+    }
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "BLK to JSON output",
+        "type": "object",
+        "additionalProperties": { "anyOf": shapes }
+    })
+}
+
+fn type_matches(value: &Value, typ: &str) -> bool {
+    match typ {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => false,
+    }
+}
+
+// Checks value against the subset of JSON Schema that schema() uses:
+// type, const, properties, required, additionalProperties, items, anyOf and minimum/maximum
+fn check(value: &Value, schema: &Value, path: &str) -> Result<()> {
+    if let Some(typ) = schema.get("type").and_then(Value::as_str)
+        && !type_matches(value, typ)
+    {
+        return Err(anyhow!("{}: expected {}, found {}", path, typ, value));
+    }
+    if let Some(expected) = schema.get("const")
+        && value != expected
+    {
+        return Err(anyhow!("{}: expected {}, found {}", path, expected, value));
+    }
+
+    if let Some(number) = value.as_f64() {
+        if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64)
+            && number < minimum
+        {
+            return Err(anyhow!("{}: {} is below the minimum {}", path, number, minimum));
+        }
+        if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64)
+            && number > maximum
+        {
+            return Err(anyhow!("{}: {} is above the maximum {}", path, number, maximum));
+        }
+    }
+
+    if let Some(options) = schema.get("anyOf").and_then(Value::as_array)
+        && !options.iter().any(|option| check(value, option, path).is_ok())
+    {
+        return Err(anyhow!("{}: matches no allowed shape", path));
+    }
+
+    if let Some(object) = value.as_object() {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(key) {
+                    return Err(anyhow!("{}: missing {:?}", path, key));
+                }
+            }
+        }
+        for (key, field) in object {
+            let field_path = format!("{}/{}", path.trim_end_matches('/'), key);
+            match (properties.and_then(|properties| properties.get(key)), schema.get("additionalProperties")) {
+                (Some(field_schema), _) => check(field, field_schema, &field_path)?,
+                (None, Some(Value::Bool(false))) => return Err(anyhow!("{}: unexpected field", field_path)),
+                (None, Some(extra)) if extra.is_object() => check(field, extra, &field_path)?,
+                _ => {}
+            }
+        }
+    }
+
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            check(item, items, &format!("{}/{}", path.trim_end_matches('/'), i))?;
+        }
+    }
+    Ok(())
+}
+
+// --validate-output: fails with the JSON pointer of the first violation;
+// grouped output (--group-by-attr) has one shape map per group
+pub fn validate(json: &str, grouped: bool, custom_shapes: &[CustomShape]) -> Result<()> {
+    let value: Value = serde_json::from_str(json)?;
+    // --with-meta output keeps the shape map under "shapes"
+    let (shapes, path) = match value.get("shapes") {
+        Some(shapes) if value.get("_meta").is_some() => (shapes, "/shapes"),
+        _ => (&value, "/"),
+    };
+    let schema = schema(custom_shapes);
+    let result = match shapes.as_object() {
+        Some(groups) if grouped => groups
+            .iter()
+            .try_for_each(|(group, shapes)| check(shapes, &schema, &format!("{}/{}", path.trim_end_matches('/'), group))),
+        _ => check(shapes, &schema, path),
+    };
+    result.map_err(|e| anyhow!("Output doesn't match the schema: {}", e))
+}
+
+// A single shape, for --stream which never holds the whole map
+pub fn validate_shape(shape: &Value, key: &str, custom_shapes: &[CustomShape]) -> Result<()> {
+    let schema = schema(custom_shapes);
+    check(shape, &schema["additionalProperties"], &format!("/{}", key))
+        .map_err(|e| anyhow!("Output doesn't match the schema: {}", e))
+}
+
+// TypeScript for a property's schema; objects shaped like a point are `Point`
+fn ts_type(schema: &Value) -> String {
+    if *schema == point() {
+        return "Point".to_string();
+    }
+    match schema.get("type").and_then(Value::as_str) {
+        Some("number" | "integer") => "number".to_string(),
+        Some("array") => format!("{}[]", ts_type(&schema["items"])),
+        Some("object") => format!("Record<string, {}>", ts_type(&schema["additionalProperties"])),
+        Some(typ) => typ.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+// One exported interface; `typ` is what its `type` holds
+fn interface(name: &str, shape: &Value, typ: &str) -> String {
+    let required: Vec<&str> = shape["required"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    let mut out = format!("\nexport interface {} {{\n", name);
+    for (key, property) in shape["properties"].as_object().into_iter().flatten() {
+        let optional = if required.contains(&key.as_str()) { "" } else { "?" };
+        let typ = if key == "type" {
+            format!("{:?}", typ)
+        } else {
+            ts_type(property)
+        };
+        out += &format!("  {}{}: {};\n", key, optional, typ);
+    }
+    out + "}\n"
+}
+
+// The type string as a name: `round-hole` gives `CustomRoundHole`, which can't
+// clash with a built-in kind's interface
+fn custom_name(typ: &str) -> String {
+    let words = typ.split(|c: char| !c.is_ascii_alphanumeric()).filter(|word| !word.is_empty());
+    let mut name = String::from("Custom");
+    for word in words {
+        name += &word[..1].to_uppercase();
+        name += &word[1..];
+    }
+    name
+}
+
+// --ts-types: one interface per shape kind, and per shapes.toml definition,
+// generated from schema() so the two can't drift apart. `type` is the
+// string this conversion writes, so the union can be narrowed on it.
+pub fn ts_types(opts: &Options) -> String {
+    let schema = schema(&opts.custom_shapes);
+    let mut out = String::from("export interface Point {\n  x: number;\n  y: number;\n  z?: number;\n}\n");
+    let mut names = Vec::new();
+    // schema() lists the kinds in SHAPE_TYPES order
+    let shapes = schema["additionalProperties"]["anyOf"].as_array().into_iter().flatten();
+    for (kind, shape) in SHAPE_TYPES.iter().zip(shapes) {
+        if *kind == "custom" {
+            for (definition, shape) in opts.custom_shapes.iter().zip(shape["anyOf"].as_array().into_iter().flatten()) {
+                let name = custom_name(definition.type_name());
+                out += &interface(&name, shape, definition.type_name());
+                names.push(name);
+            }
+            continue;
+        }
+        let name = format!("{}{}", kind[..1].to_uppercase(), &kind[1..]);
+        out += &interface(&name, shape, &opts.type_name(kind));
+        names.push(name);
+    }
+    out += &format!("\nexport type Shape = {};\n", names.join(" | "));
+    out += "\n// The output: shapes keyed by index (\"0\", \"1\", ...)\nexport type Shapes = Record<string, Shape>;";
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_custom_branch_without_definitions() {
+        let schema = schema(&[]);
+        let shapes = schema["additionalProperties"]["anyOf"].as_array().unwrap();
+        assert_eq!(shapes.len(), SHAPE_TYPES.len() - 1);
+        assert!(shapes.iter().all(|shape| shape.get("anyOf").is_none()));
+    }
+}
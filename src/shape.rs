@@ -0,0 +1,679 @@
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+use serde_json::value::RawValue;
+use std::{cell::Cell, collections::BTreeMap};
+
+#[derive(Deserialize, PartialEq, Debug)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+    // Depth, from a `z:r=` beside the point; 2D points have none
+    #[serde(default)]
+    pub z: Option<f64>,
+}
+
+thread_local! {
+    // Set by convert_text for --int-coords
+    pub static INT_COORDS: Cell<bool> = const { Cell::new(false) };
+    // Set by convert_text for --float-format: fixed decimals for every coordinate
+    pub static FLOAT_DECIMALS: Cell<Option<usize>> = const { Cell::new(None) };
+    // Set by convert_text for --lines-as-pairs
+    pub static LINES_AS_PAIRS: Cell<bool> = const { Cell::new(false) };
+}
+
+struct Coord(f64);
+
+impl Serialize for Coord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        // Whole numbers within f64's exact integer range go out as integers
+        if INT_COORDS.get() && self.0.fract() == 0.0 && self.0.abs() < 9007199254740992.0 {
+            serializer.serialize_i64(self.0 as i64)
+        } else if let Some(decimals) = FLOAT_DECIMALS.get()
+            && self.0.is_finite()
+        {
+            // serialize_f64 would drop the trailing zeros of "1.000"
+            RawValue::from_string(format!("{:.*}", decimals, self.0))
+                .map_err(serde::ser::Error::custom)?
+                .serialize(serializer)
+        } else {
+            serializer.serialize_f64(self.0)
+        }
+    }
+}
+
+impl Serialize for Point {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut point = serializer.serialize_struct("Point", 2 + usize::from(self.z.is_some()))?;
+        point.serialize_field("x", &Coord(self.x))?;
+        point.serialize_field("y", &Coord(self.y))?;
+        if let Some(z) = self.z {
+            point.serialize_field("z", &Coord(z))?;
+        }
+        point.end()
+    }
+}
+
+// --lines-as-pairs: a line with `from`/`to` coordinate arrays in place of
+// its start/end points, every other field as usual
+#[derive(Serialize)]
+struct LinePairs<'a> {
+    #[serde(skip_serializing_if = "str::is_empty")]
+    name: &'a str,
+    #[serde(rename = "type")]
+    typ: &'a str,
+    from: [Coord; 2],
+    to: [Coord; 2],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<[u8; 4]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<f64>,
+    #[serde(rename = "move", skip_serializing_if = "Option::is_none")]
+    moves: Option<bool>,
+    selected: bool,
+    #[serde(flatten)]
+    attrs: &'a Attrs,
+}
+
+// A shape as written to the output map
+pub struct Output<'a>(pub &'a Shape);
+
+impl Serialize for Output<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self.0 {
+            Shape::Line {
+                name,
+                typ,
+                start,
+                end,
+                color,
+                width,
+                moves,
+                selected,
+                attrs,
+            } if LINES_AS_PAIRS.get() => LinePairs {
+                name,
+                typ,
+                from: [Coord(start.x), Coord(start.y)],
+                to: [Coord(end.x), Coord(end.y)],
+                color: *color,
+                width: *width,
+                moves: *moves,
+                selected: *selected,
+                attrs,
+            }
+            .serialize(serializer),
+            shape => shape.serialize(serializer),
+        }
+    }
+}
+
+// Segments a curve's outline is drawn with
+const BEZIER_STEPS: usize = 16;
+
+// Optional attributes any entry may carry
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+pub struct Attrs {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opacity: Option<f64>,
+    // The --group-by-attr value, written as the shape's group key instead
+    #[serde(skip)]
+    pub group: Option<String>,
+    // Attributes this tool doesn't know, kept as `key:type` to value
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, String>,
+    // --with-raw: the entry exactly as it appears in the BLK
+    #[serde(rename = "_raw", default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+    // --centroid: Shape::centroid of the shape as written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub centroid: Option<Point>,
+    // --uuid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum Shape {
+    Line {
+        // Left empty, and so left out, under --strip-names
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        name: String,
+        #[serde(rename = "type")]
+        typ: String,
+        start: Point,
+        end: Point,
+        // RGBA
+        #[serde(skip_serializing_if = "Option::is_none")]
+        color: Option<[u8; 4]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        width: Option<f64>,
+        #[serde(rename = "move", skip_serializing_if = "Option::is_none")]
+        moves: Option<bool>,
+        selected: bool,
+        #[serde(flatten)]
+        attrs: Attrs,
+    },
+    Quad {
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        name: String,
+        #[serde(rename = "type")]
+        typ: String,
+        pos1: Point,
+        pos2: Point,
+        pos3: Point,
+        pos4: Point,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rotation: Option<f64>,
+        selected: bool,
+        #[serde(flatten)]
+        attrs: Attrs,
+    },
+    // Only made by --merge-duplicated-points-in-quad; after Quad so that
+    // untagged deserialization tries the four-corner form first
+    Triangle {
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        name: String,
+        #[serde(rename = "type")]
+        typ: String,
+        pos1: Point,
+        pos2: Point,
+        pos3: Point,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rotation: Option<f64>,
+        selected: bool,
+        #[serde(flatten)]
+        attrs: Attrs,
+    },
+    Arc {
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        name: String,
+        #[serde(rename = "type")]
+        typ: String,
+        center: Point,
+        radius: f64,
+        start_angle: f64,
+        end_angle: f64,
+        selected: bool,
+        #[serde(flatten)]
+        attrs: Attrs,
+    },
+    Polygon {
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        name: String,
+        #[serde(rename = "type")]
+        typ: String,
+        points: Vec<Point>,
+        selected: bool,
+        #[serde(flatten)]
+        attrs: Attrs,
+    },
+    // Cubic curve from p0 to p3, pulled towards p1 and p2
+    Bezier {
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        name: String,
+        #[serde(rename = "type")]
+        typ: String,
+        p0: Point,
+        p1: Point,
+        p2: Point,
+        p3: Point,
+        selected: bool,
+        #[serde(flatten)]
+        attrs: Attrs,
+    },
+    // A shapes.toml kind, its fields written under their own names. Nothing
+    // says which entry it came from, so JSON doesn't convert back into one.
+    #[serde(skip_deserializing)]
+    Custom {
+        #[serde(skip_serializing_if = "String::is_empty")]
+        name: String,
+        #[serde(rename = "type")]
+        typ: String,
+        #[serde(flatten)]
+        fields: BTreeMap<String, Field>,
+        selected: bool,
+        #[serde(flatten)]
+        attrs: Attrs,
+    },
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum Field {
+    Point(Point),
+    Scalar(f64),
+}
+
+impl Shape {
+    // Matches the entries of SHAPE_TYPES
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Shape::Line { .. } => "line",
+            Shape::Quad { .. } => "quad",
+            Shape::Triangle { .. } => "triangle",
+            Shape::Arc { .. } => "arc",
+            Shape::Polygon { .. } => "polygon",
+            Shape::Bezier { .. } => "bezier",
+            Shape::Custom { .. } => "custom",
+        }
+    }
+
+    pub fn attrs(&self) -> &Attrs {
+        match self {
+            Shape::Line { attrs, .. }
+            | Shape::Quad { attrs, .. }
+            | Shape::Triangle { attrs, .. }
+            | Shape::Arc { attrs, .. }
+            | Shape::Polygon { attrs, .. }
+            | Shape::Bezier { attrs, .. }
+            | Shape::Custom { attrs, .. } => attrs,
+        }
+    }
+
+    pub fn attrs_mut(&mut self) -> &mut Attrs {
+        match self {
+            Shape::Line { attrs, .. }
+            | Shape::Quad { attrs, .. }
+            | Shape::Triangle { attrs, .. }
+            | Shape::Arc { attrs, .. }
+            | Shape::Polygon { attrs, .. }
+            | Shape::Bezier { attrs, .. }
+            | Shape::Custom { attrs, .. } => attrs,
+        }
+    }
+
+    // The shape as a polyline; quads, triangles and polygons end back at their first corner and arcs
+    // (counterclockwise, in degrees) are sampled every few degrees, curves at even steps. Custom
+    // shapes join their points in field name order.
+    pub fn outline(&self) -> Vec<(f64, f64)> {
+        match self {
+            Shape::Line { start, end, .. } => vec![(start.x, start.y), (end.x, end.y)],
+            Shape::Quad {
+                pos1,
+                pos2,
+                pos3,
+                pos4,
+                ..
+            } => [pos1, pos2, pos3, pos4, pos1].iter().map(|p| (p.x, p.y)).collect(),
+            Shape::Triangle { pos1, pos2, pos3, .. } => [pos1, pos2, pos3, pos1].iter().map(|p| (p.x, p.y)).collect(),
+            Shape::Arc {
+                center,
+                radius,
+                start_angle,
+                end_angle,
+                ..
+            } => {
+                let sweep = end_angle - start_angle;
+                let steps = ((sweep.abs() / 5.0).ceil() as usize).max(1);
+                (0..=steps)
+                    .map(|step| {
+                        let angle = (start_angle + sweep * step as f64 / steps as f64).to_radians();
+                        (center.x + radius * angle.cos(), center.y + radius * angle.sin())
+                    })
+                    .collect()
+            }
+            Shape::Polygon { points, .. } => points.iter().chain(points.first()).map(|p| (p.x, p.y)).collect(),
+            Shape::Bezier { p0, p1, p2, p3, .. } => (0..=BEZIER_STEPS)
+                .map(|step| {
+                    let t = step as f64 / BEZIER_STEPS as f64;
+                    let u = 1.0 - t;
+                    let weights = [u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t];
+                    let at = |coord: fn(&Point) -> f64| {
+                        weights.iter().zip([p0, p1, p2, p3]).map(|(w, p)| w * coord(p)).sum::<f64>()
+                    };
+                    (at(|p| p.x), at(|p| p.y))
+                })
+                .collect(),
+            Shape::Custom { .. } => self.points().iter().map(|p| (p.x, p.y)).collect(),
+        }
+    }
+
+    // Concave and self-intersecting quads both fail; other kinds always pass
+    pub fn is_convex(&self) -> bool {
+        match self {
+            Shape::Quad {
+                pos1,
+                pos2,
+                pos3,
+                pos4,
+                ..
+            } => is_convex(&[pos1, pos2, pos3, pos4]),
+            _ => true,
+        }
+    }
+
+    // --normalize: quads go counterclockwise (with y pointing up), starting from
+    // the leftmost corner, the upper one on a tie. The corners themselves don't move.
+    pub fn normalize_winding(&mut self) {
+        let Shape::Quad {
+            pos1,
+            pos2,
+            pos3,
+            pos4,
+            ..
+        } = self
+        else {
+            return;
+        };
+        let mut corners = [pos1, pos2, pos3, pos4];
+        let area: f64 = (0..4)
+            .map(|i| corners[i].x * corners[(i + 1) % 4].y - corners[(i + 1) % 4].x * corners[i].y)
+            .sum();
+
+        let mut points: Vec<Point> = corners.iter().map(|p| Point { x: p.x, y: p.y, z: p.z }).collect();
+        if area < 0.0 {
+            points.reverse();
+        }
+        let start = (0..4)
+            .min_by(|&a, &b| points[a].x.total_cmp(&points[b].x).then(points[b].y.total_cmp(&points[a].y)))
+            .unwrap_or(0);
+        points.rotate_left(start);
+
+        for (corner, point) in corners.iter_mut().zip(points) {
+            **corner = point;
+        }
+    }
+
+    // --merge-duplicated-points-in-quad: a quad with two equal neighbouring
+    // corners becomes a triangle of the remaining three, in the same order;
+    // typ is the triangle's type string
+    pub fn merge_duplicated_points(&mut self, typ: String) {
+        let Shape::Quad {
+            name,
+            pos1,
+            pos2,
+            pos3,
+            pos4,
+            rotation,
+            selected,
+            attrs,
+            ..
+        } = self
+        else {
+            return;
+        };
+        let corners = [&*pos1, &*pos2, &*pos3, &*pos4];
+        let Some(duplicate) = (0..4).find(|&i| corners[i] == corners[(i + 1) % 4]) else {
+            return;
+        };
+        let mut kept = corners
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != duplicate)
+            .map(|(_, p)| Point { x: p.x, y: p.y, z: p.z });
+        let (Some(pos1), Some(pos2), Some(pos3)) = (kept.next(), kept.next(), kept.next()) else {
+            return;
+        };
+        *self = Shape::Triangle {
+            name: std::mem::take(name),
+            typ,
+            pos1,
+            pos2,
+            pos3,
+            rotation: *rotation,
+            selected: *selected,
+            attrs: std::mem::take(attrs),
+        };
+    }
+
+    // --simplify: Douglas–Peucker on a polygon's outline, dropping corners that
+    // lie within epsilon of the edge between the kept ones around them. A
+    // polygon that would end up with fewer than three corners is left alone.
+    // There is no polyline shape, so every other kind is left alone too.
+    pub fn simplify(&mut self, epsilon: f64) {
+        let Shape::Polygon { points, .. } = self else {
+            return;
+        };
+        if points.len() <= 3 {
+            return;
+        }
+        // Closed on the first corner, so the closing edge is simplified too
+        let ring: Vec<(f64, f64)> = points.iter().chain(points.first()).map(|p| (p.x, p.y)).collect();
+        let mut keep = douglas_peucker(&ring, epsilon);
+        keep.pop();
+        if keep.iter().filter(|kept| **kept).count() < 3 {
+            return;
+        }
+        let mut keep = keep.into_iter();
+        points.retain(|_| keep.next().unwrap_or(true));
+    }
+
+    // Average of the points that define the shape: endpoints, corners or
+    // control points; an arc's is its center
+    pub fn centroid(&self) -> Point {
+        let average = |points: &[&Point]| Point {
+            x: points.iter().map(|p| p.x).sum::<f64>() / points.len() as f64,
+            y: points.iter().map(|p| p.y).sum::<f64>() / points.len() as f64,
+            z: None,
+        };
+        match self {
+            Shape::Line { start, end, .. } => average(&[start, end]),
+            Shape::Quad {
+                pos1,
+                pos2,
+                pos3,
+                pos4,
+                ..
+            } => average(&[pos1, pos2, pos3, pos4]),
+            Shape::Triangle { pos1, pos2, pos3, .. } => average(&[pos1, pos2, pos3]),
+            Shape::Arc { center, .. } => Point {
+                x: center.x,
+                y: center.y,
+                z: None,
+            },
+            Shape::Polygon { points, .. } => average(&points.iter().collect::<Vec<_>>()),
+            Shape::Bezier { p0, p1, p2, p3, .. } => average(&[p0, p1, p2, p3]),
+            Shape::Custom { .. } => average(&self.points()),
+        }
+    }
+
+    // Every number the shape carries, for --strict-numbers
+    pub fn numbers(&self) -> Vec<f64> {
+        let xy = |points: &[&Point]| points.iter().flat_map(|p| [p.x, p.y]).collect::<Vec<_>>();
+        let mut numbers = match self {
+            Shape::Line { start, end, width, .. } => [xy(&[start, end]), Vec::from_iter(*width)].concat(),
+            Shape::Quad {
+                pos1,
+                pos2,
+                pos3,
+                pos4,
+                rotation,
+                ..
+            } => [xy(&[pos1, pos2, pos3, pos4]), Vec::from_iter(*rotation)].concat(),
+            Shape::Triangle {
+                pos1,
+                pos2,
+                pos3,
+                rotation,
+                ..
+            } => [xy(&[pos1, pos2, pos3]), Vec::from_iter(*rotation)].concat(),
+            Shape::Arc {
+                center,
+                radius,
+                start_angle,
+                end_angle,
+                ..
+            } => [xy(&[center]), vec![*radius, *start_angle, *end_angle]].concat(),
+            Shape::Polygon { points, .. } => xy(&points.iter().collect::<Vec<_>>()),
+            Shape::Bezier { p0, p1, p2, p3, .. } => xy(&[p0, p1, p2, p3]),
+            Shape::Custom { fields, .. } => fields
+                .values()
+                .flat_map(|field| match field {
+                    Field::Point(p) => vec![p.x, p.y],
+                    Field::Scalar(value) => vec![*value],
+                })
+                .collect(),
+        };
+        numbers.extend(self.attrs().opacity);
+        numbers
+    }
+
+    pub fn points(&self) -> Vec<&Point> {
+        match self {
+            Shape::Line { start, end, .. } => vec![start, end],
+            Shape::Quad {
+                pos1,
+                pos2,
+                pos3,
+                pos4,
+                ..
+            } => vec![pos1, pos2, pos3, pos4],
+            Shape::Triangle { pos1, pos2, pos3, .. } => vec![pos1, pos2, pos3],
+            Shape::Arc { center, .. } => vec![center],
+            Shape::Polygon { points, .. } => points.iter().collect(),
+            Shape::Bezier { p0, p1, p2, p3, .. } => vec![p0, p1, p2, p3],
+            Shape::Custom { fields, .. } => fields
+                .values()
+                .filter_map(|field| match field {
+                    Field::Point(p) => Some(p),
+                    Field::Scalar(_) => None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn points_mut(&mut self) -> Vec<&mut Point> {
+        match self {
+            Shape::Line { start, end, .. } => vec![start, end],
+            Shape::Quad {
+                pos1,
+                pos2,
+                pos3,
+                pos4,
+                ..
+            } => vec![pos1, pos2, pos3, pos4],
+            Shape::Triangle { pos1, pos2, pos3, .. } => vec![pos1, pos2, pos3],
+            Shape::Arc { center, .. } => vec![center],
+            Shape::Polygon { points, .. } => points.iter_mut().collect(),
+            Shape::Bezier { p0, p1, p2, p3, .. } => vec![p0, p1, p2, p3],
+            Shape::Custom { fields, .. } => fields
+                .values_mut()
+                .filter_map(|field| match field {
+                    Field::Point(p) => Some(p),
+                    Field::Scalar(_) => None,
+                })
+                .collect(),
+        }
+    }
+
+    // Mirrors the shape across the x axis, for BLKs in screen coordinates
+    // (y down). Rotations and arc angles keep counting counterclockwise.
+    pub fn flip_y(&mut self) {
+        for point in self.points_mut() {
+            // Adding zero turns the -0.0 a zero would flip to back into 0.0
+            point.y = -point.y + 0.0;
+        }
+        match self {
+            Shape::Quad { rotation, .. } | Shape::Triangle { rotation, .. } => {
+                *rotation = rotation.map(|degrees| -degrees)
+            }
+            Shape::Arc {
+                start_angle,
+                end_angle,
+                ..
+            } => (*start_angle, *end_angle) = (360.0 - *end_angle, 360.0 - *start_angle),
+            _ => {}
+        }
+    }
+
+    pub fn select(&mut self) {
+        match self {
+            Shape::Line { selected, .. }
+            | Shape::Quad { selected, .. }
+            | Shape::Triangle { selected, .. }
+            | Shape::Arc { selected, .. }
+            | Shape::Polygon { selected, .. }
+            | Shape::Bezier { selected, .. }
+            | Shape::Custom { selected, .. } => *selected = true,
+        }
+    }
+
+    pub fn set_index(&mut self, idx: usize) {
+        match self {
+            Shape::Line { name, .. } => *name = format!("Линия{idx}"),
+            Shape::Quad { name, .. } => *name = format!("Четырёхугольник{idx}"),
+            Shape::Triangle { name, .. } => *name = format!("Треугольник{idx}"),
+            Shape::Arc { name, .. } => *name = format!("Дуга{idx}"),
+            Shape::Polygon { name, .. } => *name = format!("Многоугольник{idx}"),
+            Shape::Bezier { name, .. } => *name = format!("Кривая{idx}"),
+            Shape::Custom { name, .. } => *name = format!("Фигура{idx}"),
+        }
+    }
+}
+
+// Smallest box around every outline, as (min_x, min_y, max_x, max_y)
+pub fn bounds<'a>(shapes: impl IntoIterator<Item = &'a Shape>) -> Option<(f64, f64, f64, f64)> {
+    shapes
+        .into_iter()
+        .flat_map(Shape::outline)
+        .fold(None, |acc, (x, y)| match acc {
+            None => Some((x, y, x, y)),
+            Some((min_x, min_y, max_x, max_y)) => Some((min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))),
+        })
+}
+
+// Which points of the path Douglas–Peucker keeps; the ends always are.
+// Spans are worked off a stack so a long path can't overflow the call stack.
+fn douglas_peucker(path: &[(f64, f64)], epsilon: f64) -> Vec<bool> {
+    let mut keep = vec![false; path.len()];
+    keep[0] = true;
+    keep[path.len() - 1] = true;
+    let mut spans = vec![(0, path.len() - 1)];
+    while let Some((first, last)) = spans.pop() {
+        let farthest = (first + 1..last)
+            .map(|i| (i, distance_to_segment(path[i], path[first], path[last])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, distance)) = farthest
+            && distance > epsilon
+        {
+            keep[i] = true;
+            spans.push((first, i));
+            spans.push((i, last));
+        }
+    }
+    keep
+}
+
+fn distance_to_segment(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    // A closed path starts and ends on the same point
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0)
+    };
+    (p.0 - a.0 - t * dx).hypot(p.1 - a.1 - t * dy)
+}
+
+// Every turn between consecutive edges goes the same way; straight
+// corners are allowed, but not a quad that is flat altogether
+pub fn is_convex(corners: &[&Point]) -> bool {
+    let n = corners.len();
+    let turns: Vec<f64> = (0..n)
+        .map(|i| {
+            let (a, b, c) = (corners[i], corners[(i + 1) % n], corners[(i + 2) % n]);
+            (b.x - a.x) * (c.y - b.y) - (b.y - a.y) * (c.x - b.x)
+        })
+        .filter(|turn| *turn != 0.0)
+        .collect();
+    !turns.is_empty() && (turns.iter().all(|turn| *turn > 0.0) || turns.iter().all(|turn| *turn < 0.0))
+}
+
+// Rotates points (in degrees) around their centroid
+pub fn rotate_around_centroid(points: &mut [Point], degrees: f64) {
+    let n = points.len() as f64;
+    let cx = points.iter().map(|p| p.x).sum::<f64>() / n;
+    let cy = points.iter().map(|p| p.y).sum::<f64>() / n;
+    let (sin, cos) = degrees.to_radians().sin_cos();
+
+    for p in points {
+        let (dx, dy) = (p.x - cx, p.y - cy);
+        p.x = cx + dx * cos - dy * sin;
+        p.y = cy + dx * sin + dy * cos;
+    }
+}
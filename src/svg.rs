@@ -0,0 +1,26 @@
+use crate::shape::{bounds, Shape};
+
+// Every outline as a polyline in the file's own coordinates, y pointing down
+// like the thumbnail. The view box is the bounds with a small margin, so the
+// drawing fills whatever size it is shown at; the stroke keeps its width.
+pub fn to_svg<'a>(shapes: impl IntoIterator<Item = &'a Shape> + Clone) -> String {
+    let (min_x, min_y, max_x, max_y) = bounds(shapes.clone()).unwrap_or((0.0, 0.0, 0.0, 0.0));
+    let extent = (max_x - min_x).max(max_y - min_y);
+    // A lone point (or nothing at all) has no extent to take a margin from
+    let margin = if extent > 0.0 { extent / 50.0 } else { 1.0 };
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+        min_x - margin,
+        min_y - margin,
+        max_x - min_x + 2.0 * margin,
+        max_y - min_y + 2.0 * margin
+    );
+    for shape in shapes {
+        let points: Vec<String> = shape.outline().iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+        out += &format!(
+            "  <polyline points=\"{}\" fill=\"none\" stroke=\"black\" stroke-width=\"1.5\" vector-effect=\"non-scaling-stroke\"/>\n",
+            points.join(" ")
+        );
+    }
+    out + "</svg>\n"
+}
@@ -0,0 +1,55 @@
+use anyhow::{anyhow, Result};
+use std::{io::Write, path::Path};
+use tiny_skia::{Color, Paint, PathBuilder, Pixmap, Stroke, Transform};
+
+use crate::{
+    cli,
+    shape::{bounds, Shape},
+};
+
+const SIZE: u32 = 256;
+const MARGIN: f64 = 8.0;
+
+// Draws every shape to scale in a SIZE x SIZE image, centered with a small margin
+pub fn write_png(shapes: &[&Shape], path: &Path) -> Result<()> {
+    let mut pixmap = Pixmap::new(SIZE, SIZE).ok_or_else(|| anyhow!("Couldn't create the thumbnail image"))?;
+    pixmap.fill(Color::WHITE);
+
+    if let Some((min_x, min_y, max_x, max_y)) = bounds(shapes.iter().copied()) {
+        let extent = (max_x - min_x).max(max_y - min_y);
+        let room = SIZE as f64 - 2.0 * MARGIN;
+        // A lone point (or nothing but points) has no extent to scale by
+        let scale = if extent > 0.0 { room / extent } else { 1.0 };
+        let offset_x = MARGIN + (room - (max_x - min_x) * scale) / 2.0;
+        let offset_y = MARGIN + (room - (max_y - min_y) * scale) / 2.0;
+
+        let mut paint = Paint::default();
+        paint.set_color_rgba8(0, 0, 0, 255);
+        paint.anti_alias = true;
+        let stroke = Stroke {
+            width: 1.5,
+            ..Stroke::default()
+        };
+
+        for shape in shapes {
+            let mut builder = PathBuilder::new();
+            for (i, (x, y)) in shape.outline().into_iter().enumerate() {
+                let (x, y) = ((offset_x + (x - min_x) * scale) as f32, (offset_y + (y - min_y) * scale) as f32);
+                if i == 0 {
+                    builder.move_to(x, y);
+                } else {
+                    builder.line_to(x, y);
+                }
+            }
+            // Zero-length outlines have no path to stroke
+            if let Some(path) = builder.finish() {
+                pixmap.stroke_path(&path, &paint, &stroke, Transform::identity(), None);
+            }
+        }
+    }
+
+    let png = pixmap
+        .encode_png()
+        .map_err(|e| anyhow!("Couldn't write {}: {}", path.display(), e))?;
+    cli::write_atomic(path, |file| Ok(file.write_all(&png)?))
+}